@@ -0,0 +1,223 @@
+use std::collections::{HashMap, HashSet};
+
+use data_classes::derive::*;
+use smol_str::SmolStr;
+
+use crate::ast::{Block, Diagnostic, DiagnosticCode, Document, Inline, Node, Severity, Span};
+use crate::messages::{Locale, message};
+use crate::schema::{Render, lookup_block};
+
+/// A place a cross-reference can point at: a block with an `#id`, or a
+/// section, which gets an id from its title.
+#[data]
+pub struct Anchor {
+    pub id: SmolStr,
+    /// Heading text of a section, shown by references without text.
+    pub title: Option<SmolStr>,
+    /// Span of the `#id` attribute, or of the header of a section without one.
+    pub span: Span,
+    /// Whether the id is written as `#id` rather than derived from the title.
+    pub explicit: bool,
+}
+
+/// A `@ref(id) {...}` cross-reference.
+#[data]
+pub struct Reference {
+    pub id: SmolStr,
+    pub span: Span,
+}
+
+/// Lists the anchors of `document` in document order.
+pub fn anchors(document: &Document) -> Vec<Anchor> {
+    block_anchors(document)
+        .into_iter()
+        .map(|(_, anchor)| anchor)
+        .collect()
+}
+
+/// Lists the cross-references of `document` in document order.
+pub fn references(document: &Document) -> Vec<Reference> {
+    fn visit_inlines(inlines: &[Inline], references: &mut Vec<Reference>) {
+        for inline in inlines {
+            let Inline::Marker(marker) = inline else {
+                continue;
+            };
+            if let Some(arg) = &marker.arg
+                && lookup_block(&marker.name).is_some_and(|schema| schema.render == Render::Ref)
+            {
+                references.push(Reference {
+                    id: arg.trim().into(),
+                    span: marker.span,
+                });
+            }
+            visit_inlines(&marker.nodes, references);
+        }
+    }
+    fn visit(nodes: &[Node], references: &mut Vec<Reference>) {
+        for node in nodes {
+            match node {
+                Node::Block(block) => visit(&block.nodes, references),
+                Node::Region(region) => visit(&region.nodes, references),
+                Node::Text(text) => {
+                    for line in &text.lines {
+                        visit_inlines(&line.inlines, references);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut references = Vec::new();
+    visit(&document.nodes, &mut references);
+    references
+}
+
+/// Reports ids given to more than one block and references to unknown ids.
+pub(crate) fn check_references(document: &Document, locale: Locale) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let anchors = anchors(document);
+    let mut first: HashMap<&str, Span> = HashMap::new();
+    for anchor in anchors.iter().filter(|anchor| anchor.explicit) {
+        let Some(&first) = first.get(anchor.id.as_str()) else {
+            first.insert(&anchor.id, anchor.span);
+            continue;
+        };
+        let code = DiagnosticCode::DuplicateId;
+        let args = [("id", anchor.id.as_str())];
+        diagnostics.push(
+            Diagnostic::new(
+                code,
+                Severity::Error,
+                anchor.span,
+                message(locale, code, "", &args),
+            )
+            .with_related(first, message(locale, code, "note", &args)),
+        );
+    }
+    let ids: HashSet<&str> = anchors.iter().map(|anchor| anchor.id.as_str()).collect();
+    for reference in references(document) {
+        if ids.contains(reference.id.as_str()) {
+            continue;
+        }
+        let code = DiagnosticCode::DanglingReference;
+        diagnostics.push(Diagnostic::new(
+            code,
+            Severity::Error,
+            reference.span,
+            message(locale, code, "", &[("id", &reference.id)]),
+        ));
+    }
+    diagnostics
+}
+
+/// Assigns anchors to blocks: the `#id` of any block, and a slug of the title
+/// of each section without one.
+///
+/// Slugs that are taken get a `-1`, `-2`, ... suffix, so every generated id is
+/// unique and never shadows an explicit one.
+pub(crate) fn block_anchors(document: &Document) -> Vec<(&Block, Anchor)> {
+    fn explicit(block: &Block) -> Option<(&str, Span)> {
+        block
+            .attrs
+            .iter()
+            .find(|attr| attr.key == "id")
+            .map(|attr| (attr.value.trim(), attr.span))
+            .filter(|(id, _)| !id.is_empty())
+    }
+    fn visit<'d>(nodes: &'d [Node], blocks: &mut Vec<&'d Block>) {
+        for node in nodes {
+            match node {
+                Node::Block(block) => {
+                    blocks.push(block);
+                    visit(&block.nodes, blocks);
+                }
+                Node::Region(region) => visit(&region.nodes, blocks),
+                Node::Text(_) => {}
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+    visit(&document.nodes, &mut blocks);
+    let mut used: HashSet<String> = blocks
+        .iter()
+        .filter_map(|block| explicit(block))
+        .map(|(id, _)| id.to_string())
+        .collect();
+    let mut anchors = Vec::new();
+    for block in blocks {
+        let title = is_section(block).then(|| SmolStr::from(section_title(block)));
+        let (id, span, explicit) = match (explicit(block), &title) {
+            (Some((id, span)), _) => (id.to_string(), span, true),
+            (None, Some(title)) => {
+                let base = slugify(title);
+                let mut id = base.clone();
+                let mut suffix = 0;
+                while used.contains(&id) {
+                    suffix += 1;
+                    id = format!("{base}-{suffix}");
+                }
+                used.insert(id.clone());
+                (id, block.span, false)
+            }
+            (None, None) => continue,
+        };
+        let anchor = Anchor {
+            id: id.into(),
+            title,
+            span,
+            explicit,
+        };
+        anchors.push((block, anchor));
+    }
+    anchors
+}
+
+/// Turns heading text into an anchor id.
+///
+/// Letters and digits of any script are kept, lowercased, and every other run
+/// of characters becomes a single `-`, so `Hello, World` gives `hello-world`
+/// and `块 属性` gives `块-属性`.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    let mut gap = false;
+    for ch in text.chars() {
+        if ch.is_alphanumeric() || ch == '_' {
+            if gap && !slug.is_empty() {
+                slug.push('-');
+            }
+            gap = false;
+            slug.extend(ch.to_lowercase());
+        } else {
+            gap = true;
+        }
+    }
+    if slug.is_empty() {
+        slug.push_str("section");
+    }
+    slug
+}
+
+/// The heading text of a section: its arguments, or `part` without any.
+pub(crate) fn section_title(block: &Block) -> String {
+    if block.args.is_empty() {
+        "part".to_string()
+    } else {
+        block
+            .args
+            .iter()
+            .map(|arg| arg.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Identifies a block by where its header starts, so a clone or a rebuilt
+/// copy of a block still finds its anchor.
+pub(crate) fn block_key(block: &Block) -> (usize, usize) {
+    (block.span.start.line, block.span.start.col8)
+}
+
+pub(crate) fn is_section(block: &Block) -> bool {
+    lookup_block(&block.name).is_some_and(|schema| schema.render == Render::Section)
+}
@@ -30,14 +30,221 @@ pub enum Severity {
     Warning,
 }
 
+/// Stable identifier of a kind of diagnostic, such as `LMM001`.
+#[data(copy)]
+pub enum DiagnosticCode {
+    /// No space between a block name and its `{`.
+    MissingSpace,
+    /// A block without its closing delimiter.
+    UnclosedBlock,
+    /// An inline marker without its closing `}`.
+    UnclosedInline,
+    /// Inline code without its closing ` `` `.
+    UnclosedInlineCode,
+    /// A block header without its opening `{`.
+    MissingOpenBrace,
+    /// A block header without a name.
+    MissingBlockName,
+    /// A `$` region without its closing `$` line.
+    UnterminatedRegion,
+    /// An attribute line that is not `#key: value`.
+    MalformedAttribute,
+    /// A block that is not in the schema registry.
+    UnknownBlock,
+    /// A param the block does not accept.
+    UnknownParam,
+    /// A param value the block does not accept.
+    InvalidParamValue,
+    /// Params that cannot be used together.
+    ConflictingParams,
+    /// A required attribute that is not given.
+    MissingAttribute,
+    /// A nested block its parent does not allow.
+    DisallowedChild,
+    /// An inline marker that is not in the schema registry.
+    UnknownInline,
+    /// Arguments given to a block or marker that takes none.
+    UnexpectedArgument,
+    /// A marker missing its required argument.
+    MissingArgument,
+    /// A code line indented less than its block.
+    CodeIndentation,
+    /// Indentation mixing tabs and spaces.
+    MixedIndentation,
+    /// A `$` line with more content after the `$`.
+    DollarContent,
+    /// An attribute line after the content of its block, read as text.
+    MisplacedAttribute,
+    /// An attribute given twice in the same place.
+    DuplicateAttribute,
+    /// A cross-reference to an id no block has.
+    DanglingReference,
+    /// An id given to more than one block.
+    DuplicateId,
+}
+
+impl DiagnosticCode {
+    /// Every code, in numeric order.
+    pub const ALL: &[DiagnosticCode] = &[
+        DiagnosticCode::MissingSpace,
+        DiagnosticCode::UnclosedBlock,
+        DiagnosticCode::UnclosedInline,
+        DiagnosticCode::UnclosedInlineCode,
+        DiagnosticCode::MissingOpenBrace,
+        DiagnosticCode::MissingBlockName,
+        DiagnosticCode::UnterminatedRegion,
+        DiagnosticCode::MalformedAttribute,
+        DiagnosticCode::UnknownBlock,
+        DiagnosticCode::UnknownParam,
+        DiagnosticCode::InvalidParamValue,
+        DiagnosticCode::ConflictingParams,
+        DiagnosticCode::MissingAttribute,
+        DiagnosticCode::DisallowedChild,
+        DiagnosticCode::UnknownInline,
+        DiagnosticCode::UnexpectedArgument,
+        DiagnosticCode::MissingArgument,
+        DiagnosticCode::CodeIndentation,
+        DiagnosticCode::MixedIndentation,
+        DiagnosticCode::DollarContent,
+        DiagnosticCode::MisplacedAttribute,
+        DiagnosticCode::DuplicateAttribute,
+        DiagnosticCode::DanglingReference,
+        DiagnosticCode::DuplicateId,
+    ];
+
+    /// The stable code, such as `LMM001`.
+    pub fn as_str(self) -> &'static str {
+        self.info().0
+    }
+
+    /// The short kebab-case name, such as `missing-space`.
+    pub fn name(self) -> &'static str {
+        self.info().1
+    }
+
+    fn info(self) -> (&'static str, &'static str) {
+        match self {
+            DiagnosticCode::MissingSpace => ("LMM001", "missing-space"),
+            DiagnosticCode::UnclosedBlock => ("LMM002", "unclosed-block"),
+            DiagnosticCode::UnclosedInline => ("LMM003", "unclosed-inline"),
+            DiagnosticCode::UnclosedInlineCode => ("LMM004", "unclosed-inline-code"),
+            DiagnosticCode::MissingOpenBrace => ("LMM005", "missing-open-brace"),
+            DiagnosticCode::MissingBlockName => ("LMM006", "missing-block-name"),
+            DiagnosticCode::UnterminatedRegion => ("LMM007", "unterminated-region"),
+            DiagnosticCode::MalformedAttribute => ("LMM008", "malformed-attribute"),
+            DiagnosticCode::UnknownBlock => ("LMM009", "unknown-block"),
+            DiagnosticCode::UnknownParam => ("LMM010", "unknown-param"),
+            DiagnosticCode::InvalidParamValue => ("LMM011", "invalid-param-value"),
+            DiagnosticCode::ConflictingParams => ("LMM012", "conflicting-params"),
+            DiagnosticCode::MissingAttribute => ("LMM013", "missing-attribute"),
+            DiagnosticCode::DisallowedChild => ("LMM014", "disallowed-child"),
+            DiagnosticCode::UnknownInline => ("LMM015", "unknown-inline"),
+            DiagnosticCode::UnexpectedArgument => ("LMM016", "unexpected-argument"),
+            DiagnosticCode::MissingArgument => ("LMM017", "missing-argument"),
+            DiagnosticCode::CodeIndentation => ("LMM018", "code-indentation"),
+            DiagnosticCode::MixedIndentation => ("LMM019", "mixed-indentation"),
+            DiagnosticCode::DollarContent => ("LMM020", "dollar-content"),
+            DiagnosticCode::MisplacedAttribute => ("LMM021", "misplaced-attribute"),
+            DiagnosticCode::DuplicateAttribute => ("LMM022", "duplicate-attribute"),
+            DiagnosticCode::DanglingReference => ("LMM023", "dangling-reference"),
+            DiagnosticCode::DuplicateId => ("LMM024", "duplicate-id"),
+        }
+    }
+}
+
 /// A diagnostic message tied to a source span.
 #[data]
 pub struct Diagnostic {
     pub span: Span,
     pub severity: Severity,
+    pub code: DiagnosticCode,
+    pub message: SmolStr,
+    /// Other places that explain the diagnostic, such as the header of an
+    /// unclosed block.
+    pub related: Vec<RelatedSpan>,
+    /// Machine-applicable fixes, best first.
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnostic {
+    pub fn new(
+        code: DiagnosticCode,
+        severity: Severity,
+        span: Span,
+        message: impl Into<SmolStr>,
+    ) -> Self {
+        Diagnostic {
+            span,
+            severity,
+            code,
+            message: message.into(),
+            related: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    /// Adds a related location.
+    pub fn with_related(mut self, span: Span, message: impl Into<SmolStr>) -> Self {
+        self.related.push(RelatedSpan {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds a fix made of `edits`.
+    pub fn with_fix(mut self, title: impl Into<SmolStr>, edits: Vec<Edit>) -> Self {
+        self.fixes.push(Fix {
+            title: title.into(),
+            edits,
+        });
+        self
+    }
+}
+
+/// A location related to a diagnostic.
+#[data]
+pub struct RelatedSpan {
+    pub span: Span,
     pub message: SmolStr,
 }
 
+/// A suggested fix for a diagnostic.
+#[data]
+pub struct Fix {
+    /// Short description, such as "Insert '}'".
+    pub title: SmolStr,
+    pub edits: Vec<Edit>,
+}
+
+/// A replacement of the source text covered by `span`.
+#[data]
+pub struct Edit {
+    pub span: Span,
+    pub new_text: SmolStr,
+}
+
+impl Edit {
+    /// Inserts `text` at `at`.
+    pub fn insert(at: Position, text: impl Into<SmolStr>) -> Self {
+        Edit {
+            span: Span::new(at, at),
+            new_text: text.into(),
+        }
+    }
+}
+
+/// A replacement of `start..end` bytes of a previous text by `inserted` bytes.
+#[data(copy, new)]
+pub struct TextChange {
+    /// Start of the replaced range in the previous text (byte offset).
+    pub start: usize,
+    /// End of the replaced range in the previous text (byte offset).
+    pub end: usize,
+    /// Length of the replacement text in bytes.
+    pub inserted: usize,
+}
+
 /// Parsed document root containing attributes and nodes.
 #[data]
 pub struct Document {
@@ -55,9 +262,11 @@ pub struct Attribute {
 
 /// Top-level node kinds in the document.
 #[data]
+#[allow(clippy::large_enum_variant)]
 pub enum Node {
     Block(Block),
     Text(Text),
+    Region(Region),
 }
 
 /// A block node with parameters, attributes, children, and span.
@@ -68,10 +277,58 @@ pub struct Block {
     pub params: Vec<Attribute>,
     pub attrs: Vec<Attribute>,
     pub nodes: Vec<Node>,
+    /// Number of `+` signs in the enhanced delimiter (`0` for plain `{ }`).
+    pub plus_count: usize,
+    /// Header span from `@` through the opening `{`.
     pub span: Span,
+    /// Span of the closing delimiter, or `None` if the block is unclosed.
+    pub close_span: Option<Span>,
 }
 
-/// A text node containing parsed lines.
+impl Block {
+    /// Span of the whole block, from its header through its closing
+    /// delimiter, or through its last content or attribute when it is
+    /// unclosed.
+    pub fn full_span(&self) -> Span {
+        let end = match self.close_span {
+            Some(close) => close.end,
+            None => nodes_end(&self.nodes)
+                .or_else(|| self.attrs.last().map(|attr| attr.span.end))
+                .unwrap_or(self.span.end),
+        };
+        Span::new(self.span.start, end)
+    }
+}
+
+/// Where the last of `nodes` ends, closing delimiters included.
+fn nodes_end(nodes: &[Node]) -> Option<Position> {
+    match nodes.last()? {
+        Node::Text(text) => text.lines.last().map(|line| line.span.end),
+        Node::Block(block) => Some(block.full_span().end),
+        Node::Region(region) => match region.close_span {
+            Some(close) => Some(close.end),
+            None => nodes_end(&region.nodes).or(Some(region.span.end)),
+        },
+    }
+}
+
+/// Paragraphs between a pair of `$` lines.
+///
+/// The content belongs to the enclosing block like its other nodes, but is
+/// written without being indented under the block's header.
+#[data]
+pub struct Region {
+    pub nodes: Vec<Node>,
+    /// Span of the opening `$`.
+    pub span: Span,
+    /// Span of the closing `$`, or `None` if the region is unterminated.
+    pub close_span: Option<Span>,
+}
+
+/// A paragraph of consecutive non-blank lines.
+///
+/// Blank lines separate paragraphs; inside code blocks they are kept as empty
+/// lines instead.
 #[data]
 pub struct Text {
     pub lines: Vec<TextLine>,
@@ -82,6 +339,38 @@ pub struct Text {
 pub struct TextLine {
     pub indent: usize,
     pub value: SmolStr,
+    pub inlines: Vec<Inline>,
     pub span: Span,
     pub is_comment: bool,
 }
+
+/// Inline content parsed from a text line.
+#[data]
+pub enum Inline {
+    Text(InlineText),
+    Code(InlineCode),
+    Marker(InlineMarker),
+}
+
+/// Plain inline text with escapes resolved.
+#[data]
+pub struct InlineText {
+    pub value: SmolStr,
+    pub span: Span,
+}
+
+/// Inline code delimited by double backticks, kept verbatim.
+#[data]
+pub struct InlineCode {
+    pub value: SmolStr,
+    pub span: Span,
+}
+
+/// An inline marker such as `@b {...}` or `@link(url) {...}`.
+#[data]
+pub struct InlineMarker {
+    pub name: SmolStr,
+    pub arg: Option<SmolStr>,
+    pub nodes: Vec<Inline>,
+    pub span: Span,
+}
@@ -1,91 +1,492 @@
-use crate::ast::{Attribute, Block, Document, Node, Text, TextLine};
+use std::collections::HashMap;
+
+use data_classes::derive::*;
+use smol_str::SmolStr;
+
+use crate::anchor::{block_anchors, block_key, section_title};
+use crate::ast::{Attribute, Block, Document, Inline, InlineMarker, Node, Text, TextLine};
+use crate::outline::{OutlineEntry, outline};
+use crate::schema::{Render, lookup_block};
+
+/// Options for [`render_html_with_options`].
+#[data(default)]
+pub struct HtmlOptions {
+    /// Emit a full HTML page titled from `#title` instead of a fragment.
+    pub standalone: bool,
+    /// Wrap the content in a `<div>` carrying the document attributes.
+    #[default = true]
+    pub wrapper: bool,
+    /// Stylesheet URLs linked before the content.
+    pub stylesheets: Vec<SmolStr>,
+    /// Script URLs loaded after the content.
+    pub scripts: Vec<SmolStr>,
+    /// Prefix of every class name, as in `lmm-part`.
+    #[default = SmolStr::new_static("lmm-")]
+    pub class_prefix: SmolStr,
+    /// Emit `#key` attributes as `data-key`.
+    #[default = true]
+    pub data_attrs: bool,
+    /// Emit block params as `data-param-key`.
+    #[default = true]
+    pub data_params: bool,
+    /// Levels added to every section heading, so `1` starts at `<h2>`.
+    pub heading_offset: usize,
+    /// Make section headings links to their own section.
+    #[default = true]
+    pub heading_links: bool,
+    /// Put each element on a line of its own; off for compact output.
+    #[default = true]
+    pub pretty: bool,
+}
+
+/// Options for [`render_markdown_with_options`].
+#[data(default, copy)]
+pub struct MarkdownOptions {
+    /// How section ids are written next to headings.
+    #[default = MarkdownAnchors::None]
+    pub anchors: MarkdownAnchors,
+}
+
+/// Ways of giving a Markdown heading an explicit anchor.
+#[data(copy)]
+pub enum MarkdownAnchors {
+    /// Plain headings, leaving anchors to the Markdown renderer.
+    None,
+    /// A `{#id}` attribute after the heading, as in Pandoc and kramdown.
+    /// Ids that cannot be written there, such as ones with spaces, are left
+    /// out.
+    Attribute,
+    /// An `<a id="id"></a>` line before the heading.
+    Html,
+}
+
+/// Renders blocks of one name, registered in [`Renderers`].
+///
+/// Each method defaults to the built-in rendering of the block, so a handler
+/// only needs to implement the formats it changes.
+pub trait Renderer: Send + Sync {
+    /// Renders `block` as HTML.
+    fn html(&self, block: &Block, cx: &mut RenderContext<'_>) {
+        cx.render_builtin(block);
+    }
+
+    /// Renders `block` as Markdown.
+    fn markdown(&self, block: &Block, cx: &mut RenderContext<'_>) {
+        cx.render_builtin(block);
+    }
+}
+
+/// Handlers for block names, used instead of the built-in rendering.
+///
+/// Blocks without a handler render as built in; unknown blocks become a
+/// generic container.
+#[derive(Default)]
+pub struct Renderers {
+    handlers: HashMap<SmolStr, Box<dyn Renderer>>,
+}
+
+impl Renderers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `renderer` for blocks named `name`, replacing any earlier
+    /// handler or built-in rendering.
+    pub fn register(&mut self, name: impl Into<SmolStr>, renderer: impl Renderer + 'static) {
+        self.handlers.insert(name.into(), Box::new(renderer));
+    }
+
+    fn get(&self, name: &str) -> Option<&dyn Renderer> {
+        self.handlers.get(name).map(|renderer| renderer.as_ref())
+    }
+
+    pub fn render_markdown(&self, document: &Document) -> String {
+        self.render_markdown_with_options(document, MarkdownOptions::default())
+    }
+
+    pub fn render_markdown_with_options(
+        &self,
+        document: &Document,
+        options: MarkdownOptions,
+    ) -> String {
+        let mut out = String::new();
+        let html = HtmlOptions::default();
+        let anchors = Anchors::new(document);
+        let mut cx = RenderContext::new(&mut out, self, Format::Markdown, &html, &anchors);
+        cx.markdown = options;
+        cx.render_nodes(&document.nodes);
+        trim_trailing_newlines(&mut out);
+        out
+    }
+
+    pub fn render_html(&self, document: &Document) -> String {
+        self.render_html_with_options(document, &HtmlOptions::default())
+    }
+
+    pub fn render_html_with_options(&self, document: &Document, options: &HtmlOptions) -> String {
+        let mut out = String::new();
+        let anchors = Anchors::new(document);
+        let mut cx = RenderContext::new(&mut out, self, Format::Html, options, &anchors);
+        if options.standalone {
+            for line in [
+                "<!DOCTYPE html>",
+                "<html>",
+                "<head>",
+                "<meta charset=\"utf-8\">",
+            ] {
+                cx.push_str(line);
+                cx.push_newline();
+            }
+            if let Some(title) = document.attrs.iter().find(|attr| attr.key == "title") {
+                cx.push_str("<title>");
+                cx.push_text(&title.value);
+                cx.push_str("</title>");
+                cx.push_newline();
+            }
+        }
+        for href in &options.stylesheets {
+            cx.push_str("<link rel=\"stylesheet\" href=\"");
+            cx.push_text(href);
+            cx.push_str("\">");
+            cx.push_newline();
+        }
+        if options.standalone {
+            for line in ["</head>", "<body>"] {
+                cx.push_str(line);
+                cx.push_newline();
+            }
+        }
+        if options.wrapper {
+            cx.push_str("<div");
+            cx.push_class(&["document"]);
+            push_data_attrs(&mut cx, &document.attrs, None);
+            cx.push_str(">");
+            cx.push_newline();
+        }
+        cx.render_nodes(&document.nodes);
+        if options.wrapper {
+            cx.push_str("</div>");
+            cx.push_newline();
+        }
+        for src in &options.scripts {
+            cx.push_str("<script src=\"");
+            cx.push_text(src);
+            cx.push_str("\"></script>");
+            cx.push_newline();
+        }
+        if options.standalone {
+            for line in ["</body>", "</html>"] {
+                cx.push_str(line);
+                cx.push_newline();
+            }
+        }
+        out
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Html,
+    Markdown,
+}
+
+/// Output being rendered, handed to a [`Renderer`].
+pub struct RenderContext<'r> {
+    out: &'r mut String,
+    renderers: &'r Renderers,
+    format: Format,
+    html: &'r HtmlOptions,
+    markdown: MarkdownOptions,
+    anchors: &'r Anchors,
+    part_level: usize,
+}
+
+impl<'r> RenderContext<'r> {
+    fn new(
+        out: &'r mut String,
+        renderers: &'r Renderers,
+        format: Format,
+        html: &'r HtmlOptions,
+        anchors: &'r Anchors,
+    ) -> Self {
+        RenderContext {
+            out,
+            renderers,
+            format,
+            html,
+            markdown: MarkdownOptions::default(),
+            anchors,
+            part_level: 0,
+        }
+    }
+
+    /// Options of the HTML being rendered.
+    pub fn html_options(&self) -> &HtmlOptions {
+        self.html
+    }
+
+    /// The anchor id of `block`: its `#id`, or a slug of the title of a section.
+    pub fn block_id(&self, block: &Block) -> Option<&str> {
+        self.anchors.block_id(block)
+    }
+
+    /// Appends `text` as is.
+    pub fn push_str(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    /// Appends `text`, escaped for the output format.
+    pub fn push_text(&mut self, text: &str) {
+        match self.format {
+            Format::Html => escape_html_into(self.out, text),
+            Format::Markdown => escape_markdown_into(self.out, text),
+        }
+    }
+
+    /// Ends a line, unless rendering compact HTML.
+    pub fn push_newline(&mut self) {
+        if self.format == Format::Markdown || self.html.pretty {
+            self.out.push('\n');
+        }
+    }
+
+    /// Appends a `class` attribute holding `names` with the class prefix.
+    pub fn push_class(&mut self, names: &[&str]) {
+        self.out.push_str(" class=\"");
+        for (index, name) in names.iter().enumerate() {
+            if index > 0 {
+                self.out.push(' ');
+            }
+            escape_html_into(self.out, &self.html.class_prefix);
+            escape_html_into(self.out, name);
+        }
+        self.out.push('"');
+    }
+
+    /// Appends the anchor id of `block` as an `id` attribute, and its other
+    /// attributes and params as `data-*` attributes.
+    pub fn push_html_attrs(&mut self, block: &Block) {
+        let anchors = self.anchors;
+        if let Some(id) = anchors.block_id(block) {
+            self.out.push_str(" id=\"");
+            escape_html_into(self.out, id);
+            self.out.push('"');
+        }
+        push_data_attrs(self, &block.attrs, Some(&block.params));
+    }
+
+    /// Renders `nodes`, dispatching nested blocks to their handlers.
+    pub fn render_nodes(&mut self, nodes: &[Node]) {
+        match self.format {
+            Format::Html => render_nodes_html(nodes, self),
+            Format::Markdown => render_nodes_markdown(nodes, self),
+        }
+    }
+
+    /// Renders `nodes` as the body of a section, one heading level deeper.
+    pub fn render_nested(&mut self, nodes: &[Node]) {
+        self.at_part_level(self.part_level + 1, |cx| cx.render_nodes(nodes));
+    }
+
+    /// Renders the inline content of a text line.
+    pub fn render_inlines(&mut self, inlines: &[Inline]) {
+        match self.format {
+            Format::Html => render_inlines_html(inlines, self),
+            Format::Markdown => render_inlines_markdown(inlines, self),
+        }
+    }
+
+    /// Renders `block` as built in, ignoring any handler registered for it.
+    pub fn render_builtin(&mut self, block: &Block) {
+        match self.format {
+            Format::Html => render_builtin_html(block, self),
+            Format::Markdown => render_builtin_markdown(block, self),
+        }
+    }
+
+    fn render_block(&mut self, block: &Block) {
+        let renderers = self.renderers;
+        match (renderers.get(&block.name), self.format) {
+            (Some(renderer), Format::Html) => renderer.html(block, self),
+            (Some(renderer), Format::Markdown) => renderer.markdown(block, self),
+            (None, _) => self.render_builtin(block),
+        }
+    }
+
+    fn at_part_level(&mut self, level: usize, render: impl FnOnce(&mut Self)) {
+        let outer = std::mem::replace(&mut self.part_level, level);
+        render(self);
+        self.part_level = outer;
+    }
+}
 
 pub fn render_markdown(document: &Document) -> String {
-    let mut out = String::new();
-    render_nodes_markdown(&document.nodes, &mut out, 0);
-    trim_trailing_newlines(&mut out);
-    out
+    Renderers::new().render_markdown(document)
+}
+
+pub fn render_markdown_with_options(document: &Document, options: MarkdownOptions) -> String {
+    Renderers::new().render_markdown_with_options(document, options)
 }
 
 pub fn render_html(document: &Document) -> String {
-    let mut out = String::new();
-    out.push_str("<div class=\"lmm-document\"");
-    push_html_attrs(&mut out, &document.attrs, None);
-    out.push_str(">\n");
-    render_nodes_html(&document.nodes, &mut out, 0);
-    out.push_str("</div>\n");
-    out
+    Renderers::new().render_html(document)
+}
+
+pub fn render_html_with_options(document: &Document, options: &HtmlOptions) -> String {
+    Renderers::new().render_html_with_options(document, options)
+}
+
+/// Counts the sections enclosing a block whose ancestors are `ancestors`,
+/// outermost first, the way the renderers do: blocks inside a list start
+/// over at the top level.
+pub fn section_depth(ancestors: &[&Block]) -> usize {
+    let start = ancestors
+        .iter()
+        .rposition(|block| block_render(&block.name) == Some(Render::List))
+        .map_or(0, |index| index + 1);
+    ancestors[start..]
+        .iter()
+        .filter(|block| block_render(&block.name) == Some(Render::Section))
+        .count()
 }
 
-fn render_nodes_markdown(nodes: &[Node], out: &mut String, part_level: usize) {
+/// The heading level of a section inside `depth` others, after adding
+/// `offset` levels; headings stop at level 6.
+pub fn heading_level(depth: usize, offset: usize) -> usize {
+    (depth + 1 + offset).min(6)
+}
+
+/// Anchor ids of the blocks of a document, the text references to them show
+/// by default, and the outline tables of contents list.
+struct Anchors {
+    /// Ids keyed by where their block starts.
+    ids: HashMap<(usize, usize), SmolStr>,
+    /// Default reference text keyed by id; the first block wins a shared id.
+    texts: HashMap<SmolStr, SmolStr>,
+    outline: Vec<OutlineEntry>,
+}
+
+impl Anchors {
+    fn new(document: &Document) -> Self {
+        let mut ids = HashMap::new();
+        let mut texts = HashMap::new();
+        for (block, anchor) in block_anchors(document) {
+            let text = anchor.title.unwrap_or_else(|| anchor.id.clone());
+            texts.entry(anchor.id.clone()).or_insert(text);
+            ids.insert(block_key(block), anchor.id);
+        }
+        Anchors {
+            ids,
+            texts,
+            outline: outline(document),
+        }
+    }
+
+    fn block_id(&self, block: &Block) -> Option<&str> {
+        self.ids.get(&block_key(block)).map(SmolStr::as_str)
+    }
+}
+
+/// Looks up how the built-in schema of `name` is rendered.
+fn block_render(name: &str) -> Option<Render> {
+    lookup_block(name).map(|schema| schema.render)
+}
+
+fn render_nodes_markdown(nodes: &[Node], cx: &mut RenderContext<'_>) {
     for node in nodes {
         match node {
-            Node::Text(text) => render_text_markdown(text, out),
-            Node::Block(block) => render_block_markdown(block, out, part_level),
+            Node::Text(text) => render_text_markdown(text, cx),
+            Node::Block(block) => cx.render_block(block),
+            Node::Region(region) => render_nodes_markdown(&region.nodes, cx),
         }
     }
 }
 
-fn render_block_markdown(block: &Block, out: &mut String, part_level: usize) {
-    match block.name.as_str() {
-        "part" => {
-            let level = (part_level + 1).min(6);
-            let title = if block.args.is_empty() {
-                "part".to_string()
-            } else {
-                block
-                    .args
-                    .iter()
-                    .map(|arg| arg.as_str())
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            };
-            out.push_str(&"#".repeat(level));
-            out.push(' ');
-            out.push_str(&title);
-            out.push_str("\n\n");
-            render_nodes_markdown(&block.nodes, out, part_level + 1);
-        }
-        "list" => {
+fn render_builtin_markdown(block: &Block, cx: &mut RenderContext<'_>) {
+    match block_render(&block.name) {
+        Some(Render::Section) => {
+            let level = heading_level(cx.part_level, 0);
+            let title = section_title(block);
+            let id = cx.block_id(block).unwrap_or_default().to_string();
+            if cx.markdown.anchors == MarkdownAnchors::Html {
+                cx.out.push_str("<a id=\"");
+                escape_html_into(cx.out, &id);
+                cx.out.push_str("\"></a>\n\n");
+            }
+            cx.out.push_str(&"#".repeat(level));
+            cx.out.push(' ');
+            escape_markdown_into(cx.out, &title);
+            if cx.markdown.anchors == MarkdownAnchors::Attribute && is_attribute_id(&id) {
+                cx.out.push_str(" {#");
+                cx.out.push_str(&id);
+                cx.out.push('}');
+            }
+            cx.out.push_str("\n\n");
+            cx.render_nested(&block.nodes);
+        }
+        Some(Render::List) => {
             let style = list_style(block);
-            render_list_markdown(block, out, style);
-        }
-        "code" => {
-            let lang = block
-                .params
-                .iter()
-                .find(|param| param.key.as_str() == "lang")
-                .map(|param| param.value.as_str())
-                .unwrap_or("");
-            out.push_str("```");
+            render_list_markdown(block, cx, style);
+        }
+        Some(Render::Code) => {
+            let lang = code_lang(block);
+            cx.out.push_str("```");
             if !lang.is_empty() {
-                out.push_str(lang);
+                cx.out.push_str(lang);
+            }
+            cx.out.push('\n');
+            render_text_only_markdown(&block.nodes, cx.out);
+            cx.out.push_str("```\n\n");
+        }
+        Some(Render::Toc) => {
+            let anchors = cx.anchors;
+            if render_toc_markdown(&anchors.outline, cx, toc_depth(block), 0) {
+                cx.out.push('\n');
             }
-            out.push('\n');
-            render_text_only_markdown(&block.nodes, out);
-            out.push_str("```\n\n");
         }
         _ => {
-            render_nodes_markdown(&block.nodes, out, part_level);
+            render_nodes_markdown(&block.nodes, cx);
         }
     }
 }
 
-fn render_text_markdown(text: &Text, out: &mut String) {
-    for line in &text.lines {
-        if line.is_comment {
-            continue;
-        }
-        push_indent(out, line.indent);
-        out.push_str(line.value.as_str());
-        out.push('\n');
+fn code_lang(block: &Block) -> &str {
+    block
+        .params
+        .iter()
+        .find(|param| param.key.as_str() == "lang")
+        .map(|param| param.value.as_str())
+        .unwrap_or("")
+}
+
+fn render_text_markdown(text: &Text, cx: &mut RenderContext<'_>) {
+    let mut had_text = false;
+    // Indentation would turn deeply nested paragraphs into Markdown code blocks.
+    for line in text.lines.iter().filter(|line| !line.is_comment) {
+        had_text = true;
+        render_inlines_markdown(&line.inlines, cx);
+        cx.out.push('\n');
+    }
+    if had_text {
+        cx.out.push('\n');
     }
-    out.push('\n');
 }
 
-fn render_list_markdown(block: &Block, out: &mut String, style: ListStyle) {
+fn render_list_markdown(block: &Block, cx: &mut RenderContext<'_>, style: ListStyle) {
+    if render_list_items_markdown(&block.nodes, cx, style) {
+        cx.out.push('\n');
+    }
+}
+
+/// Renders each text line of `nodes` as a list item, returning whether there
+/// was any.
+fn render_list_items_markdown(
+    nodes: &[Node],
+    cx: &mut RenderContext<'_>,
+    style: ListStyle,
+) -> bool {
     let mut had_text = false;
-    for node in &block.nodes {
+    for node in nodes {
         match node {
             Node::Text(text) => {
                 for line in &text.lines {
@@ -95,180 +496,523 @@ fn render_list_markdown(block: &Block, out: &mut String, style: ListStyle) {
                     had_text = true;
                     match style {
                         ListStyle::Bullet => {
-                            out.push_str("- ");
-                            out.push_str(line.value.as_str());
-                            out.push('\n');
+                            cx.out.push_str("- ");
+                            render_inlines_markdown(&line.inlines, cx);
+                            cx.out.push('\n');
                         }
                         ListStyle::Line => {
-                            out.push_str(line.value.as_str());
-                            out.push('\n');
+                            render_inlines_markdown(&line.inlines, cx);
+                            cx.out.push('\n');
                         }
                     }
                 }
             }
-            Node::Block(child) => render_block_markdown(child, out, 0),
+            Node::Block(child) => cx.at_part_level(0, |cx| cx.render_block(child)),
+            Node::Region(region) => {
+                had_text |= render_list_items_markdown(&region.nodes, cx, style);
+            }
         }
     }
-    if had_text {
-        out.push('\n');
+    had_text
+}
+
+/// Renders `entries` as a bullet list of links nested by `indent`, returning
+/// whether there was any.
+fn render_toc_markdown(
+    entries: &[OutlineEntry],
+    cx: &mut RenderContext<'_>,
+    depth: usize,
+    indent: usize,
+) -> bool {
+    let mut had_entry = false;
+    for entry in entries.iter().filter(|entry| entry.level <= depth) {
+        had_entry = true;
+        push_indent(cx.out, indent);
+        cx.out.push_str("- [");
+        escape_markdown_into(cx.out, &entry.title);
+        cx.out.push_str("](#");
+        push_markdown_url(cx.out, &entry.id);
+        cx.out.push_str(")\n");
+        render_toc_markdown(&entry.children, cx, depth, indent + 2);
     }
+    had_entry
 }
 
 fn render_text_only_markdown(nodes: &[Node], out: &mut String) {
-    for node in nodes {
-        if let Node::Text(text) = node {
-            for line in &text.lines {
-                if line.is_comment {
-                    continue;
-                }
-                out.push_str(line.value.as_str());
-                out.push('\n');
-            }
-        }
+    for (indent, value) in code_lines(nodes) {
+        push_indent(out, indent);
+        out.push_str(value);
+        out.push('\n');
     }
 }
 
-fn render_nodes_html(nodes: &[Node], out: &mut String, part_level: usize) {
+/// Collects the lines of a code block with their common indentation removed,
+/// keeping blank lines between them.
+fn code_lines(nodes: &[Node]) -> Vec<(usize, &str)> {
+    let lines: Vec<&TextLine> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            Node::Text(text) => Some(&text.lines),
+            Node::Block(_) | Node::Region(_) => None,
+        })
+        .flatten()
+        .filter(|line| !line.is_comment)
+        .collect();
+    let start = lines
+        .iter()
+        .position(|line| !line.value.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|line| !line.value.is_empty())
+        .map_or(start, |index| index + 1);
+    let lines = &lines[start..end];
+    let base = lines
+        .iter()
+        .filter(|line| !line.value.is_empty())
+        .map(|line| line.indent)
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| {
+            if line.value.is_empty() {
+                (0, "")
+            } else {
+                (line.indent - base, line.value.as_str())
+            }
+        })
+        .collect()
+}
+
+fn render_nodes_html(nodes: &[Node], cx: &mut RenderContext<'_>) {
     for node in nodes {
         match node {
-            Node::Text(text) => render_text_html(text, out),
-            Node::Block(block) => render_block_html(block, out, part_level),
+            Node::Text(text) => render_text_html(text, cx),
+            Node::Block(block) => cx.render_block(block),
+            Node::Region(region) => render_nodes_html(&region.nodes, cx),
         }
     }
 }
 
-fn render_block_html(block: &Block, out: &mut String, part_level: usize) {
-    match block.name.as_str() {
-        "part" => {
-            let level = (part_level + 1).min(6);
-            let title = if block.args.is_empty() {
-                "part".to_string()
-            } else {
-                block
-                    .args
-                    .iter()
-                    .map(|arg| arg.as_str())
-                    .collect::<Vec<_>>()
-                    .join(" ")
-            };
-            out.push_str("<section class=\"lmm-part\"");
-            push_html_attrs(out, &block.attrs, Some(&block.params));
-            out.push_str(">\n");
-            out.push_str(&format!("<h{level}>", level = level));
-            escape_html_into(out, &title);
-            out.push_str(&format!("</h{level}>\n", level = level));
-            render_nodes_html(&block.nodes, out, part_level + 1);
-            out.push_str("</section>\n");
-        }
-        "list" => {
+fn render_builtin_html(block: &Block, cx: &mut RenderContext<'_>) {
+    match block_render(&block.name) {
+        Some(Render::Section) => {
+            let level = heading_level(cx.part_level, cx.html.heading_offset);
+            let title = section_title(block);
+            cx.push_str("<section");
+            cx.push_class(&["part"]);
+            cx.push_html_attrs(block);
+            cx.push_str(">");
+            cx.push_newline();
+            cx.push_str(&format!("<h{level}>", level = level));
+            match cx.block_id(block).filter(|_| cx.html.heading_links) {
+                Some(id) => {
+                    let href = format!("#{id}");
+                    cx.push_str("<a");
+                    cx.push_class(&["anchor"]);
+                    cx.push_str(" href=\"");
+                    cx.push_text(&href);
+                    cx.push_str("\">");
+                    cx.push_text(&title);
+                    cx.push_str("</a>");
+                }
+                None => cx.push_text(&title),
+            }
+            cx.push_str(&format!("</h{level}>", level = level));
+            cx.push_newline();
+            cx.render_nested(&block.nodes);
+            cx.push_str("</section>");
+            cx.push_newline();
+        }
+        Some(Render::List) => {
             let style = list_style(block);
-            render_list_html(block, out, style);
-        }
-        "code" => {
-            let lang = block
-                .params
-                .iter()
-                .find(|param| param.key.as_str() == "lang")
-                .map(|param| param.value.as_str())
-                .unwrap_or("");
-            out.push_str("<pre class=\"lmm-code\"");
-            push_html_attrs(out, &block.attrs, Some(&block.params));
-            out.push_str("><code");
+            render_list_html(block, cx, style);
+        }
+        Some(Render::Code) => {
+            let lang = code_lang(block);
+            cx.push_str("<pre");
+            cx.push_class(&["code"]);
+            cx.push_html_attrs(block);
+            cx.push_str("><code");
             if !lang.is_empty() {
-                out.push_str(" class=\"language-");
-                escape_html_into(out, lang);
-                out.push('\"');
+                cx.push_str(" class=\"language-");
+                cx.push_text(lang);
+                cx.push_str("\"");
             }
-            out.push_str(">");
-            render_text_only_html(&block.nodes, out);
-            out.push_str("</code></pre>\n");
+            cx.push_str(">");
+            render_text_only_html(&block.nodes, cx.out);
+            cx.push_str("</code></pre>");
+            cx.push_newline();
+        }
+        Some(Render::Toc) => {
+            let anchors = cx.anchors;
+            cx.push_str("<nav");
+            cx.push_class(&["toc"]);
+            cx.push_html_attrs(block);
+            cx.push_str(">");
+            cx.push_newline();
+            render_toc_html(&anchors.outline, cx, toc_depth(block));
+            cx.push_str("</nav>");
+            cx.push_newline();
         }
         _ => {
-            let class_name = format!(
-                "lmm-block lmm-block-{}",
-                sanitize_html_ident(block.name.as_str())
-            );
-            out.push_str("<div class=\"");
-            out.push_str(&class_name);
-            out.push('\"');
-            push_html_attrs(out, &block.attrs, Some(&block.params));
-            out.push_str(">\n");
-            render_nodes_html(&block.nodes, out, part_level);
-            out.push_str("</div>\n");
+            let name = format!("block-{}", sanitize_html_ident(block.name.as_str()));
+            cx.push_str("<div");
+            cx.push_class(&["block", &name]);
+            cx.push_html_attrs(block);
+            cx.push_str(">");
+            cx.push_newline();
+            render_nodes_html(&block.nodes, cx);
+            cx.push_str("</div>");
+            cx.push_newline();
         }
     }
 }
 
-fn render_text_html(text: &Text, out: &mut String) {
-    for line in &text.lines {
-        if line.is_comment {
-            continue;
+fn render_text_html(text: &Text, cx: &mut RenderContext<'_>) {
+    let mut lines = text.lines.iter().filter(|line| !line.is_comment);
+    let Some(first) = lines.next() else {
+        return;
+    };
+    cx.push_str("<p>");
+    render_inlines_html(&first.inlines, cx);
+    for line in lines {
+        cx.push_str("\n");
+        render_inlines_html(&line.inlines, cx);
+    }
+    cx.push_str("</p>");
+    cx.push_newline();
+}
+
+fn render_toc_html(entries: &[OutlineEntry], cx: &mut RenderContext<'_>, depth: usize) {
+    let mut entries = entries
+        .iter()
+        .filter(|entry| entry.level <= depth)
+        .peekable();
+    if entries.peek().is_none() {
+        return;
+    }
+    cx.push_str("<ul>");
+    cx.push_newline();
+    for entry in entries {
+        cx.push_str("<li><a href=\"#");
+        cx.push_text(&entry.id);
+        cx.push_str("\">");
+        cx.push_text(&entry.title);
+        cx.push_str("</a>");
+        if entry.children.iter().any(|child| child.level <= depth) {
+            cx.push_newline();
+            render_toc_html(&entry.children, cx, depth);
         }
-        out.push_str("<p>");
-        escape_html_into(out, line.value.as_str());
-        out.push_str("</p>\n");
-    }
-}
-
-fn render_list_html(block: &Block, out: &mut String, style: ListStyle) {
-    match style {
-        ListStyle::Bullet => {
-            out.push_str("<ul class=\"lmm-list\"");
-            push_html_attrs(out, &block.attrs, Some(&block.params));
-            out.push_str(">\n");
-            for node in &block.nodes {
-                match node {
-                    Node::Text(text) => {
-                        for line in &text.lines {
-                            if line.is_comment {
-                                continue;
-                            }
-                            out.push_str("<li>");
-                            escape_html_into(out, line.value.as_str());
-                            out.push_str("</li>\n");
-                        }
+        cx.push_str("</li>");
+        cx.push_newline();
+    }
+    cx.push_str("</ul>");
+    cx.push_newline();
+}
+
+fn render_list_html(block: &Block, cx: &mut RenderContext<'_>, style: ListStyle) {
+    let (tag, class) = match style {
+        ListStyle::Bullet => ("ul", "list"),
+        ListStyle::Line => ("div", "lines"),
+    };
+    cx.push_str("<");
+    cx.push_str(tag);
+    cx.push_class(&[class]);
+    cx.push_html_attrs(block);
+    cx.push_str(">");
+    cx.push_newline();
+    render_list_items_html(&block.nodes, cx, style);
+    cx.push_str("</");
+    cx.push_str(tag);
+    cx.push_str(">");
+    cx.push_newline();
+}
+
+fn render_list_items_html(nodes: &[Node], cx: &mut RenderContext<'_>, style: ListStyle) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => {
+                for line in &text.lines {
+                    if line.is_comment {
+                        continue;
                     }
-                    Node::Block(child) => render_block_html(child, out, 0),
-                }
-            }
-            out.push_str("</ul>\n");
-        }
-        ListStyle::Line => {
-            out.push_str("<div class=\"lmm-lines\"");
-            push_html_attrs(out, &block.attrs, Some(&block.params));
-            out.push_str(">\n");
-            for node in &block.nodes {
-                match node {
-                    Node::Text(text) => {
-                        for line in &text.lines {
-                            if line.is_comment {
-                                continue;
-                            }
-                            out.push_str("<div class=\"lmm-line\">");
-                            escape_html_into(out, line.value.as_str());
-                            out.push_str("</div>\n");
+                    match style {
+                        ListStyle::Bullet => cx.push_str("<li>"),
+                        ListStyle::Line => {
+                            cx.push_str("<div");
+                            cx.push_class(&["line"]);
+                            cx.push_str(">");
                         }
                     }
-                    Node::Block(child) => render_block_html(child, out, 0),
+                    render_inlines_html(&line.inlines, cx);
+                    cx.push_str(match style {
+                        ListStyle::Bullet => "</li>",
+                        ListStyle::Line => "</div>",
+                    });
+                    cx.push_newline();
                 }
             }
-            out.push_str("</div>\n");
+            Node::Block(child) => cx.at_part_level(0, |cx| cx.render_block(child)),
+            Node::Region(region) => render_list_items_html(&region.nodes, cx, style),
         }
     }
 }
 
 fn render_text_only_html(nodes: &[Node], out: &mut String) {
-    for node in nodes {
-        if let Node::Text(text) = node {
-            for line in &text.lines {
-                if line.is_comment {
-                    continue;
+    for (indent, value) in code_lines(nodes) {
+        push_indent(out, indent);
+        escape_html_into(out, value);
+        out.push('\n');
+    }
+}
+
+fn render_inlines_markdown(inlines: &[Inline], cx: &mut RenderContext<'_>) {
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => escape_markdown_into(cx.out, &text.value),
+            Inline::Code(code) => push_markdown_code(cx.out, code.value.as_str()),
+            Inline::Marker(marker) => render_marker_markdown(marker, cx),
+        }
+    }
+}
+
+fn render_marker_markdown(marker: &InlineMarker, cx: &mut RenderContext<'_>) {
+    match block_render(&marker.name) {
+        Some(Render::Bold) => {
+            cx.out.push_str("**");
+            render_inlines_markdown(&marker.nodes, cx);
+            cx.out.push_str("**");
+        }
+        Some(Render::Italic) => {
+            cx.out.push('*');
+            render_inlines_markdown(&marker.nodes, cx);
+            cx.out.push('*');
+        }
+        Some(Render::Underline) => {
+            cx.out.push_str("<u>");
+            render_inlines_markdown(&marker.nodes, cx);
+            cx.out.push_str("</u>");
+        }
+        Some(Render::Link) => match marker.arg.as_deref().and_then(sanitize_link_url) {
+            Some(url) => {
+                cx.out.push('[');
+                render_inlines_markdown(&marker.nodes, cx);
+                cx.out.push_str("](");
+                push_markdown_url(cx.out, url);
+                cx.out.push(')');
+            }
+            None => render_inlines_markdown(&marker.nodes, cx),
+        },
+        Some(Render::Ref) => match ref_target(marker, cx) {
+            Some((id, text)) => {
+                cx.out.push('[');
+                match text {
+                    Some(text) => escape_markdown_into(cx.out, text),
+                    None => render_inlines_markdown(&marker.nodes, cx),
+                }
+                cx.out.push_str("](#");
+                push_markdown_url(cx.out, id);
+                cx.out.push(')');
+            }
+            None => render_inlines_markdown(&marker.nodes, cx),
+        },
+        _ => render_inlines_markdown(&marker.nodes, cx),
+    }
+}
+
+/// Appends `text` with the characters Markdown would read as emphasis,
+/// links, code, raw HTML or entities escaped by a backslash. At the start of
+/// a line, markers that would begin a heading, list, quote or fence are
+/// escaped too.
+fn escape_markdown_into(out: &mut String, text: &str) {
+    let marker = if at_markdown_line_start(out) {
+        line_marker(text)
+    } else {
+        None
+    };
+    for (idx, ch) in text.char_indices() {
+        if matches!(ch, '\\' | '*' | '_' | '[' | ']' | '`' | '<' | '>' | '&') || marker == Some(idx)
+        {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+}
+
+/// Returns whether text appended to `out` starts a line's content, possibly
+/// after a list item or quote marker.
+fn at_markdown_line_start(out: &str) -> bool {
+    let line = &out[out.rfind('\n').map_or(0, |idx| idx + 1)..];
+    let line = line.trim();
+    line.is_empty()
+        || matches!(line, "-" | "*" | "+" | ">")
+        || line
+            .strip_suffix(['.', ')'])
+            .is_some_and(|number| number.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Byte offset of the character that makes the start of `text` a Markdown
+/// block marker, such as the `#` of a heading or the `.` of `1.`.
+fn line_marker(text: &str) -> Option<usize> {
+    match text.chars().next()? {
+        '#' | '-' | '+' | '=' | '~' | '>' => Some(0),
+        '0'..='9' => {
+            let digits = text.bytes().take_while(u8::is_ascii_digit).count();
+            text[digits..].starts_with(['.', ')']).then_some(digits)
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether `id` can be written as a `{#id}` heading attribute.
+fn is_attribute_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || matches!(ch, '-' | '_' | '.' | ':'))
+}
+
+/// Appends `url` with parentheses, angle brackets and whitespace
+/// percent-encoded, so it cannot end the link destination early.
+fn push_markdown_url(out: &mut String, url: &str) {
+    for ch in url.chars() {
+        match ch {
+            '(' => out.push_str("%28"),
+            ')' => out.push_str("%29"),
+            '<' => out.push_str("%3C"),
+            '>' => out.push_str("%3E"),
+            ch if ch.is_whitespace() => {
+                let mut buf = [0; 4];
+                for byte in ch.encode_utf8(&mut buf).bytes() {
+                    out.push_str(&format!("%{byte:02X}"));
+                }
+            }
+            ch => out.push(ch),
+        }
+    }
+}
+
+/// Resolves the target of a `@ref` marker to its id and, when the marker has
+/// no content of its own, the default text; `None` for an unknown id.
+fn ref_target<'c>(
+    marker: &InlineMarker,
+    cx: &RenderContext<'c>,
+) -> Option<(&'c str, Option<&'c str>)> {
+    let id = marker.arg.as_deref()?.trim();
+    let (id, text) = cx.anchors.texts.get_key_value(id)?;
+    Some((id, marker.nodes.is_empty().then_some(text.as_str())))
+}
+
+fn push_markdown_code(out: &mut String, value: &str) {
+    let mut longest = 0usize;
+    let mut run = 0usize;
+    for ch in value.chars() {
+        if ch == '`' {
+            run += 1;
+            longest = longest.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    let fence = "`".repeat(longest + 1);
+    let pad = value.starts_with('`') || value.ends_with('`');
+    out.push_str(&fence);
+    if pad {
+        out.push(' ');
+    }
+    out.push_str(value);
+    if pad {
+        out.push(' ');
+    }
+    out.push_str(&fence);
+}
+
+fn render_inlines_html(inlines: &[Inline], cx: &mut RenderContext<'_>) {
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => cx.push_text(text.value.as_str()),
+            Inline::Code(code) => {
+                cx.push_str("<code>");
+                cx.push_text(code.value.as_str());
+                cx.push_str("</code>");
+            }
+            Inline::Marker(marker) => render_marker_html(marker, cx),
+        }
+    }
+}
+
+fn render_marker_html(marker: &InlineMarker, cx: &mut RenderContext<'_>) {
+    let tag = match block_render(&marker.name) {
+        Some(Render::Bold) => "strong",
+        Some(Render::Italic) => "em",
+        Some(Render::Underline) => "u",
+        Some(Render::Link) => {
+            match marker.arg.as_deref().and_then(sanitize_link_url) {
+                Some(url) => {
+                    cx.push_str("<a href=\"");
+                    cx.push_text(url);
+                    cx.push_str("\">");
+                    render_inlines_html(&marker.nodes, cx);
+                    cx.push_str("</a>");
                 }
-                escape_html_into(out, line.value.as_str());
-                out.push('\n');
+                None => render_inlines_html(&marker.nodes, cx),
             }
+            return;
+        }
+        Some(Render::Ref) => {
+            match ref_target(marker, cx) {
+                Some((id, text)) => {
+                    cx.push_str("<a");
+                    cx.push_class(&["ref"]);
+                    cx.push_str(" href=\"#");
+                    cx.push_text(id);
+                    cx.push_str("\">");
+                    match text {
+                        Some(text) => cx.push_text(text),
+                        None => render_inlines_html(&marker.nodes, cx),
+                    }
+                    cx.push_str("</a>");
+                }
+                None => render_inlines_html(&marker.nodes, cx),
+            }
+            return;
+        }
+        _ => {
+            let name = format!("inline-{}", sanitize_html_ident(marker.name.as_str()));
+            cx.push_str("<span");
+            cx.push_class(&[&name]);
+            cx.push_str(">");
+            render_inlines_html(&marker.nodes, cx);
+            cx.push_str("</span>");
+            return;
         }
+    };
+    cx.push_str("<");
+    cx.push_str(tag);
+    cx.push_str(">");
+    render_inlines_html(&marker.nodes, cx);
+    cx.push_str("</");
+    cx.push_str(tag);
+    cx.push_str(">");
+}
+
+/// Returns the trimmed link target, or `None` for schemes that can run script.
+fn sanitize_link_url(url: &str) -> Option<&str> {
+    let url = url.trim();
+    let normalized = url
+        .chars()
+        .filter(|ch| !ch.is_ascii_whitespace() && !ch.is_ascii_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let blocked = ["javascript:", "vbscript:", "data:"]
+        .iter()
+        .any(|scheme| normalized.starts_with(scheme));
+    if url.is_empty() || blocked {
+        return None;
     }
+    Some(url)
 }
 
 fn push_indent(out: &mut String, indent: usize) {
@@ -293,6 +1037,16 @@ fn list_style(block: &Block) -> ListStyle {
     ListStyle::Bullet
 }
 
+/// The deepest section level a `@toc` lists.
+fn toc_depth(block: &Block) -> usize {
+    block
+        .params
+        .iter()
+        .find(|param| param.key.as_str() == "depth")
+        .and_then(|param| param.value.trim().parse().ok())
+        .unwrap_or(usize::MAX)
+}
+
 fn has_param(block: &Block, key: &str) -> bool {
     block.params.iter().any(|param| param.key.as_str() == key)
         || block.args.iter().any(|arg| arg.as_str() == key)
@@ -304,30 +1058,30 @@ fn trim_trailing_newlines(out: &mut String) {
     }
 }
 
-fn push_html_attrs(out: &mut String, attrs: &[Attribute], params: Option<&[Attribute]>) {
-    for attr in attrs {
+/// Appends `attrs` as `data-key` and the `params` of a block as
+/// `data-param-key`, as far as the HTML options allow.
+///
+/// The `#id` of a block is left out, as it becomes the `id` attribute.
+fn push_data_attrs(cx: &mut RenderContext<'_>, attrs: &[Attribute], params: Option<&[Attribute]>) {
+    let attrs = attrs
+        .iter()
+        .filter(|attr| cx.html.data_attrs && !(params.is_some() && attr.key == "id"))
+        .map(|attr| (" data-", attr));
+    let params = params
+        .unwrap_or_default()
+        .iter()
+        .filter(|_| cx.html.data_params)
+        .map(|param| (" data-param-", param));
+    for (prefix, attr) in attrs.chain(params) {
         let key = sanitize_html_ident(attr.key.as_str());
         if key.is_empty() {
             continue;
         }
-        out.push_str(" data-");
-        out.push_str(&key);
-        out.push_str("=\"");
-        escape_html_into(out, attr.value.as_str());
-        out.push('\"');
-    }
-    if let Some(params) = params {
-        for param in params {
-            let key = sanitize_html_ident(param.key.as_str());
-            if key.is_empty() {
-                continue;
-            }
-            out.push_str(" data-param-");
-            out.push_str(&key);
-            out.push_str("=\"");
-            escape_html_into(out, param.value.as_str());
-            out.push('\"');
-        }
+        cx.out.push_str(prefix);
+        cx.out.push_str(&key);
+        cx.out.push_str("=\"");
+        escape_html_into(cx.out, attr.value.as_str());
+        cx.out.push('\"');
     }
 }
 
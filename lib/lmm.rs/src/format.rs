@@ -0,0 +1,370 @@
+use data_classes::derive::*;
+
+use crate::ast::{Block, Edit, Node, Position, Region, Span};
+use crate::parser::{
+    ParseOptions, ParseResult, parse_document_with_options, position_for_line_offset,
+    span_for_line_offsets,
+};
+use crate::schema::is_raw_block;
+
+#[data(default, copy)]
+pub struct FormatOptions {
+    /// Indent nested blocks with one tab instead of spaces.
+    pub use_tabs: bool,
+    /// Spaces per nesting level when `use_tabs` is off.
+    #[default = 2]
+    pub indent_width: usize,
+    /// Widths used to measure existing indentation inside code blocks.
+    pub parse: ParseOptions,
+}
+
+/// Formats an LMM document into its canonical layout.
+///
+/// Nested blocks are re-indented one level per block, `@name{` becomes
+/// `@name {`, and code blocks keep their indentation relative to their
+/// least-indented line. Comments, `$` regions and enhanced delimiters are
+/// kept as written, and so is everything from an unclosed code block on.
+/// Each line keeps its line ending.
+pub fn format_document(input: &str, options: FormatOptions) -> String {
+    let parsed = parse_document_with_options(input, options.parse);
+    let mut formatted = format_lines(input, &parsed, options);
+    while formatted.last().is_some_and(|line| line.is_empty()) {
+        formatted.pop();
+    }
+    let breaks = line_breaks(input);
+    let mut out = String::new();
+    for (line, line_break) in formatted.iter().zip(breaks) {
+        out.push_str(line);
+        out.push_str(line_break);
+    }
+    out
+}
+
+/// Returns the minimal edits that turn `input` into its formatted form.
+///
+/// Each changed line yields one edit covering only the differing bytes,
+/// so unchanged text (and editor state anchored to it) is left alone.
+pub fn format_edits(input: &str, options: FormatOptions) -> Vec<Edit> {
+    let parsed = parse_document_with_options(input, options.parse);
+    format_parsed_edits(input, &parsed, options)
+}
+
+/// Like [`format_edits`], reusing an existing parse of `input`.
+pub fn format_parsed_edits(input: &str, parsed: &ParseResult, options: FormatOptions) -> Vec<Edit> {
+    let lines: Vec<&str> = input.lines().collect();
+    let formatted = format_lines(input, parsed, options);
+    let kept = formatted
+        .iter()
+        .rposition(|line| !line.is_empty())
+        .map_or(0, |index| index + 1);
+    let mut edits = Vec::new();
+    for (index, (old, new)) in lines.iter().zip(&formatted).take(kept).enumerate() {
+        edits.extend(line_edit(index, old, new));
+    }
+
+    let tail_ok = kept == lines.len() && (kept == 0 || input.ends_with('\n'));
+    if !tail_ok {
+        let (start, new_text) = match kept {
+            0 => (Position::new(0, 0, 0, 0), ""),
+            _ => {
+                let line = lines[kept - 1];
+                let start = position_for_line_offset(kept - 1, line, line.len());
+                (start, line_breaks(input)[kept - 1])
+            }
+        };
+        edits.push(Edit {
+            span: Span::new(start, document_end(input, &lines)),
+            new_text: new_text.into(),
+        });
+    }
+    edits
+}
+
+/// Returns formatting edits limited to lines `start_line..=end_line`.
+pub fn format_range_edits(
+    input: &str,
+    start_line: usize,
+    end_line: usize,
+    options: FormatOptions,
+) -> Vec<Edit> {
+    let parsed = parse_document_with_options(input, options.parse);
+    format_parsed_range_edits(input, &parsed, start_line, end_line, options)
+}
+
+/// Like [`format_range_edits`], reusing an existing parse of `input`.
+pub fn format_parsed_range_edits(
+    input: &str,
+    parsed: &ParseResult,
+    start_line: usize,
+    end_line: usize,
+    options: FormatOptions,
+) -> Vec<Edit> {
+    let lines: Vec<&str> = input.lines().collect();
+    let formatted = format_lines(input, parsed, options);
+    let mut edits = Vec::new();
+    for (index, (old, new)) in lines.iter().zip(&formatted).enumerate() {
+        if index < start_line || index > end_line {
+            continue;
+        }
+        edits.extend(line_edit(index, old, new));
+    }
+    edits
+}
+
+fn format_lines(input: &str, parsed: &ParseResult, options: FormatOptions) -> Vec<String> {
+    let lines: Vec<&str> = input.lines().collect();
+    let roles = plan_lines(parsed, &lines, options);
+    lines
+        .iter()
+        .zip(roles)
+        .map(|(line, role)| format_line(line, role, options))
+        .collect()
+}
+
+/// The line break written after each line of `input`: its own, or for a
+/// last line without one, `\r\n` if the document uses it anywhere.
+fn line_breaks(input: &str) -> Vec<&str> {
+    let newline = if input.contains("\r\n") { "\r\n" } else { "\n" };
+    input
+        .split_inclusive('\n')
+        .map(|line| match line.strip_suffix('\n') {
+            Some(content) if content.ends_with('\r') => "\r\n",
+            Some(_) => "\n",
+            None => newline,
+        })
+        .collect()
+}
+
+fn document_end(input: &str, lines: &[&str]) -> Position {
+    match lines.last() {
+        Some(_) if input.ends_with('\n') => Position::new(lines.len(), 0, 0, 0),
+        Some(last) => position_for_line_offset(lines.len() - 1, last, last.len()),
+        None => Position::new(0, 0, 0, 0),
+    }
+}
+
+/// Replaces only the bytes between the common prefix and suffix of a line.
+fn line_edit(index: usize, old: &str, new: &str) -> Option<Edit> {
+    if old == new {
+        return None;
+    }
+    let prefix = old
+        .char_indices()
+        .zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((idx, _), _)| idx)
+        .unwrap_or(old.len().min(new.len()));
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(ch, _)| ch.len_utf8())
+        .scan(0usize, |total, len| {
+            *total += len;
+            Some(*total)
+        })
+        .take_while(|total| *total <= max_suffix)
+        .last()
+        .unwrap_or(0);
+    Some(Edit {
+        span: span_for_line_offsets(index, old, prefix, old.len() - suffix),
+        new_text: new[prefix..new.len() - suffix].into(),
+    })
+}
+
+#[derive(Copy, Clone, Debug)]
+enum LineRole {
+    /// Ordinary line re-indented to `depth`.
+    Normal { depth: usize },
+    /// Block header line; `brace` is the byte offset of its opening `{`.
+    Header { depth: usize, brace: Option<usize> },
+    /// Code line that keeps indentation beyond `strip` columns.
+    Code { depth: usize, strip: usize },
+    /// Line written back exactly as-is.
+    Verbatim,
+}
+
+fn plan_lines(parsed: &ParseResult, lines: &[&str], options: FormatOptions) -> Vec<LineRole> {
+    let mut roles = vec![LineRole::Normal { depth: 0 }; lines.len()];
+    plan_nodes(&parsed.document.nodes, lines, &mut roles, 0, options);
+    // Where an unclosed raw block ends depends on how its header and the
+    // lines after it are indented, so everything from its header on is kept
+    // as written.
+    if let Some(line) = unclosed_raw_line(&parsed.document.nodes) {
+        for role in roles.iter_mut().skip(line) {
+            *role = LineRole::Verbatim;
+        }
+    }
+    roles
+}
+
+fn plan_nodes(
+    nodes: &[Node],
+    lines: &[&str],
+    roles: &mut [LineRole],
+    depth: usize,
+    options: FormatOptions,
+) {
+    for node in nodes {
+        match node {
+            Node::Block(block) => plan_block(block, lines, roles, depth, options),
+            Node::Region(region) => plan_region(region, roles),
+            Node::Text(_) => {}
+        }
+    }
+}
+
+fn plan_block(
+    block: &Block,
+    lines: &[&str],
+    roles: &mut [LineRole],
+    depth: usize,
+    options: FormatOptions,
+) {
+    let header_line = block.span.start.line;
+    let brace_line = block.span.end.line;
+    let body_start = brace_line + 1;
+    let body_end = match block.close_span {
+        Some(close)
+            if close.start.line > brace_line
+                && close.start.col8 <= leading_ws_len(lines[close.start.line]) =>
+        {
+            roles[close.start.line] = LineRole::Normal { depth };
+            close.start.line
+        }
+        Some(close) => close.start.line + 1,
+        // Stop where the parser recovered, after the last line of content.
+        None => (block.full_span().end.line + 1).max(body_start),
+    };
+    let brace = (header_line == brace_line).then(|| block.span.end.col8 - 1);
+    roles[header_line] = LineRole::Header { depth, brace };
+    for role in roles.iter_mut().take(brace_line + 1).skip(header_line + 1) {
+        *role = LineRole::Normal { depth: depth + 1 };
+    }
+
+    if is_raw_block(&block.name) {
+        let strip = lines[body_start..body_end]
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| indent_columns(line, options.parse))
+            .min()
+            .unwrap_or(0);
+        for role in roles.iter_mut().take(body_end).skip(body_start) {
+            *role = LineRole::Code {
+                depth: depth + 1,
+                strip,
+            };
+        }
+        return;
+    }
+
+    for role in roles.iter_mut().take(body_end).skip(body_start) {
+        *role = LineRole::Normal { depth: depth + 1 };
+    }
+    plan_nodes(&block.nodes, lines, roles, depth + 1, options);
+}
+
+/// The header line of the first raw block in `nodes` that is never closed.
+fn unclosed_raw_line(nodes: &[Node]) -> Option<usize> {
+    nodes.iter().find_map(|node| match node {
+        Node::Block(block) if block.close_span.is_none() && is_raw_block(&block.name) => {
+            Some(block.span.start.line)
+        }
+        Node::Block(block) => unclosed_raw_line(&block.nodes),
+        Node::Region(_) | Node::Text(_) => None,
+    })
+}
+
+/// Marks the lines between the `$` lines of `region` as verbatim, up to the
+/// end of the document when it is unterminated.
+fn plan_region(region: &Region, roles: &mut [LineRole]) {
+    let end = region
+        .close_span
+        .map_or(roles.len(), |close| close.start.line);
+    for role in roles.iter_mut().take(end).skip(region.span.start.line + 1) {
+        *role = LineRole::Verbatim;
+    }
+}
+
+fn format_line(line: &str, role: LineRole, options: FormatOptions) -> String {
+    let mut out = String::new();
+    if let LineRole::Verbatim = role {
+        out.push_str(line);
+        return out;
+    }
+    if line.trim().is_empty() {
+        return out;
+    }
+    match role {
+        LineRole::Normal { depth } => {
+            push_indent(&mut out, depth, options);
+            out.push_str(line.trim());
+        }
+        LineRole::Header { depth, brace } => {
+            push_indent(&mut out, depth, options);
+            match brace {
+                Some(brace) => push_header(&mut out, line, brace),
+                None => out.push_str(line.trim()),
+            }
+        }
+        LineRole::Code { depth, strip } => {
+            push_indent(&mut out, depth, options);
+            out.push_str(strip_columns(line, strip, options.parse));
+        }
+        LineRole::Verbatim => {}
+    }
+    out
+}
+
+/// Writes a header line with exactly one space before its `+{` delimiter.
+fn push_header(out: &mut String, line: &str, brace: usize) {
+    let plus_start = line[..brace].trim_end_matches('+').len();
+    let head = line[..plus_start].trim();
+    out.push_str(head);
+    out.push(' ');
+    out.push_str(line[plus_start..].trim_end());
+}
+
+fn push_indent(out: &mut String, depth: usize, options: FormatOptions) {
+    for _ in 0..depth {
+        if options.use_tabs {
+            out.push('\t');
+        } else {
+            out.push_str(&" ".repeat(options.indent_width));
+        }
+    }
+}
+
+fn leading_ws_len(line: &str) -> usize {
+    line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+fn indent_columns(line: &str, options: ParseOptions) -> usize {
+    let mut columns = 0usize;
+    for ch in line.chars() {
+        match ch {
+            ' ' => columns += options.space_width,
+            '\t' => columns += options.tab_width,
+            _ => break,
+        }
+    }
+    columns
+}
+
+/// Removes up to `columns` columns of leading whitespace.
+fn strip_columns(line: &str, columns: usize, options: ParseOptions) -> &str {
+    let mut seen = 0usize;
+    for (idx, ch) in line.char_indices() {
+        if seen >= columns {
+            return &line[idx..];
+        }
+        match ch {
+            ' ' => seen += options.space_width,
+            '\t' => seen += options.tab_width,
+            _ => return &line[idx..],
+        }
+    }
+    ""
+}
@@ -0,0 +1,357 @@
+use data_classes::derive::*;
+use smol_str::SmolStr;
+
+use crate::ast::{
+    Diagnostic, DiagnosticCode, Edit, Inline, InlineCode, InlineMarker, InlineText, Position,
+    Severity, Span,
+};
+use crate::messages::{Locale, message};
+use crate::parser::advance_position;
+use crate::schema::{BlockKind, lookup_block};
+
+/// Returns whether `name` is a built-in inline marker, which stays inline even
+/// at the start of a line.
+pub(crate) fn is_inline_marker(name: &str) -> bool {
+    lookup_block(name).is_some_and(|schema| schema.kind == BlockKind::Inline)
+}
+
+/// Returns the marker name if `text` starts with `@name`.
+pub(crate) fn marker_name(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix('@')?;
+    let len = rest
+        .bytes()
+        .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'-')
+        .count();
+    if len == 0 {
+        return None;
+    }
+    Some(&rest[..len])
+}
+
+/// An escape sequence in raw text.
+///
+/// A run of two or more `{`, `@` or `#` stands for one character fewer, so
+/// `{{` is `{` and `{{{` is `{{`. The same holds for runs of `}` outside inline
+/// markers, where a single `}` would close the block, and for runs of `!` at
+/// the start of a line, where a single `!` starts a comment.
+#[data]
+pub struct Escape {
+    /// Byte offset where the escape starts.
+    pub start: usize,
+    /// Byte offset just past the escape.
+    pub end: usize,
+    /// Text the escape stands for.
+    pub value: SmolStr,
+}
+
+/// Finds the escape sequences in one line of raw text.
+///
+/// A leading `!!` (after indentation) only counts when `line_start` is set.
+/// Inline code is verbatim and holds no escapes.
+pub fn scan_escapes(line: &str, line_start: bool) -> Vec<Escape> {
+    let mut escapes = Vec::new();
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let mut idx = 0;
+    let mut depth = 0usize;
+    while idx < line.len() {
+        let rest = &line[idx..];
+        if let Some(len) = escape_len(rest, depth == 0, line_start && idx == indent) {
+            escapes.push(Escape {
+                start: idx,
+                end: idx + len,
+                value: rest[1..len].into(),
+            });
+            idx += len;
+            continue;
+        }
+        if let Some(body) = rest.strip_prefix("``") {
+            idx += body.find("``").map_or(rest.len(), |end| 2 + end + 2);
+            continue;
+        }
+        if let Some(header) = scan_marker_header(rest) {
+            depth += 1;
+            idx += header.len;
+            continue;
+        }
+        if rest.starts_with('}') {
+            depth = depth.saturating_sub(1);
+        }
+        idx += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    escapes
+}
+
+/// Resolves the escape sequences in one line of raw text.
+pub(crate) fn unescape(line: &str, line_start: bool) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut last = 0;
+    for escape in scan_escapes(line, line_start) {
+        out.push_str(&line[last..escape.start]);
+        out.push_str(&escape.value);
+        last = escape.end;
+    }
+    out.push_str(&line[last..]);
+    out
+}
+
+/// Returns the byte length of the escape run at the start of `text`, if any.
+///
+/// `}` runs only escape outside inline markers (`top_level`) and `!` runs only
+/// at the start of a line.
+fn escape_len(text: &str, top_level: bool, line_start: bool) -> Option<usize> {
+    let first = text.bytes().next()?;
+    let escapable = match first {
+        b'{' | b'@' | b'#' => true,
+        b'}' => top_level,
+        b'!' => line_start,
+        _ => false,
+    };
+    let len = text.bytes().take_while(|b| *b == first).count();
+    (escapable && len >= 2).then_some(len)
+}
+
+pub(crate) fn parse_inlines(
+    raw: &str,
+    start: Position,
+    line_start: bool,
+    locale: Locale,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Inline> {
+    let mut parser = InlineParser {
+        input: raw,
+        idx: 0,
+        pos: start,
+        line_start,
+        locale,
+        diagnostics,
+    };
+    parser.parse_nodes(false).0
+}
+
+/// Finds the byte offset of `close` in `line` at inline nesting depth zero.
+///
+/// Braces opened by inline markers and the contents of inline code are
+/// skipped, so `@b {x}` does not end the enclosing block.
+pub(crate) fn find_inline_close(line: &str, close: &str) -> Option<usize> {
+    let mut idx = 0usize;
+    let mut depth = 0usize;
+    while idx < line.len() {
+        let rest = &line[idx..];
+        if let Some(len) = escape_len(rest, depth == 0, false) {
+            idx += len;
+            continue;
+        }
+        if let Some(body) = rest.strip_prefix("``") {
+            let end = body.find("``")?;
+            idx += 2 + end + 2;
+            continue;
+        }
+        if let Some(header) = scan_marker_header(rest) {
+            depth += 1;
+            idx += header.len;
+            continue;
+        }
+        if rest.starts_with('}') {
+            if depth > 0 {
+                depth -= 1;
+                idx += 1;
+                continue;
+            }
+            if rest.starts_with(close) {
+                return Some(idx);
+            }
+        }
+        idx += rest.chars().next().map(char::len_utf8).unwrap_or(1);
+    }
+    None
+}
+
+struct InlineParser<'a, 'd> {
+    input: &'a str,
+    idx: usize,
+    pos: Position,
+    line_start: bool,
+    locale: Locale,
+    diagnostics: &'d mut Vec<Diagnostic>,
+}
+
+impl<'a> InlineParser<'a, '_> {
+    fn parse_nodes(&mut self, nested: bool) -> (Vec<Inline>, bool) {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        let mut text_start = self.pos;
+
+        while let Some(ch) = self.peek() {
+            let rest = self.rest();
+            let line_start = self.line_start && self.idx == 0;
+            if let Some(len) = escape_len(rest, !nested, line_start) {
+                if text.is_empty() {
+                    text_start = self.pos;
+                }
+                text.push_str(&rest[1..len]);
+                self.advance_bytes(len);
+                continue;
+            }
+            if rest.starts_with("``") {
+                flush_text(&mut nodes, &mut text, text_start, self.pos);
+                nodes.push(self.parse_code());
+                continue;
+            }
+            if let Some(header) = scan_marker_header(rest) {
+                flush_text(&mut nodes, &mut text, text_start, self.pos);
+                nodes.push(self.parse_marker(header));
+                continue;
+            }
+            if ch == '}' && nested {
+                flush_text(&mut nodes, &mut text, text_start, self.pos);
+                self.bump();
+                return (nodes, true);
+            }
+            if text.is_empty() {
+                text_start = self.pos;
+            }
+            text.push(ch);
+            self.bump();
+        }
+
+        flush_text(&mut nodes, &mut text, text_start, self.pos);
+        (nodes, false)
+    }
+
+    fn parse_code(&mut self) -> Inline {
+        let start = self.pos;
+        self.advance_bytes(2);
+        let body = self.rest();
+        let (value, closed) = match body.find("``") {
+            Some(end) => (&body[..end], true),
+            None => (body, false),
+        };
+        self.advance_bytes(value.len());
+        if closed {
+            self.advance_bytes(2);
+        } else {
+            let span = Span::new(start, self.pos);
+            let diagnostic = Diagnostic::new(
+                DiagnosticCode::UnclosedInlineCode,
+                Severity::Error,
+                span,
+                message(self.locale, DiagnosticCode::UnclosedInlineCode, "", &[]),
+            )
+            .with_fix(
+                message(self.locale, DiagnosticCode::UnclosedInlineCode, "fix", &[]),
+                vec![Edit::insert(self.pos, "``")],
+            );
+            self.diagnostics.push(diagnostic);
+        }
+        Inline::Code(InlineCode {
+            value: value.into(),
+            span: Span::new(start, self.pos),
+        })
+    }
+
+    fn parse_marker(&mut self, header: MarkerHeader<'a>) -> Inline {
+        let start = self.pos;
+        self.advance_bytes(header.len);
+        let header_span = Span::new(start, self.pos);
+        let (nodes, closed) = self.parse_nodes(true);
+        if !closed {
+            let diagnostic = Diagnostic::new(
+                DiagnosticCode::UnclosedInline,
+                Severity::Error,
+                header_span,
+                message(self.locale, DiagnosticCode::UnclosedInline, "", &[]),
+            )
+            .with_fix(
+                message(self.locale, DiagnosticCode::UnclosedInline, "fix", &[]),
+                vec![Edit::insert(self.pos, "}")],
+            );
+            self.diagnostics.push(diagnostic);
+        }
+        Inline::Marker(InlineMarker {
+            name: header.name.into(),
+            arg: header.arg.map(SmolStr::from),
+            nodes,
+            span: Span::new(start, self.pos),
+        })
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.idx..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) {
+        if let Some(ch) = self.peek() {
+            self.idx += ch.len_utf8();
+            self.pos = advance_position(self.pos, ch);
+        }
+    }
+
+    fn advance_bytes(&mut self, len: usize) {
+        let target = self.idx + len;
+        while self.idx < target {
+            self.bump();
+        }
+    }
+}
+
+struct MarkerHeader<'a> {
+    name: &'a str,
+    arg: Option<&'a str>,
+    len: usize,
+}
+
+/// Recognizes `@name {`, `@name(arg) {` and their space-less forms.
+fn scan_marker_header(text: &str) -> Option<MarkerHeader<'_>> {
+    let name = marker_name(text)?;
+    let mut cursor = 1 + name.len();
+    let mut arg = None;
+    if text[cursor..].starts_with('(') {
+        let end = find_arg_end(&text[cursor..])?;
+        arg = Some(&text[cursor + 1..cursor + end]);
+        cursor += end + 1;
+    }
+    cursor += text[cursor..]
+        .bytes()
+        .take_while(|b| *b == b' ' || *b == b'\t')
+        .count();
+    if !text[cursor..].starts_with('{') {
+        return None;
+    }
+    Some(MarkerHeader {
+        name,
+        arg,
+        len: cursor + 1,
+    })
+}
+
+/// Finds the `)` matching the leading `(`, allowing balanced parentheses inside.
+fn find_arg_end(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (idx, ch) in text.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn flush_text(nodes: &mut Vec<Inline>, text: &mut String, start: Position, end: Position) {
+    if text.is_empty() {
+        return;
+    }
+    nodes.push(Inline::Text(InlineText {
+        value: std::mem::take(text).into(),
+        span: Span::new(start, end),
+    }));
+}
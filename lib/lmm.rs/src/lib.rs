@@ -1,16 +1,72 @@
+mod anchor;
 mod ast;
 mod backend;
+mod format;
+mod inline;
+mod lower;
+mod messages;
+mod outline;
 mod parser;
+mod reparse;
+mod schema;
+mod syntax;
 
+pub use crate::anchor::{Anchor, Reference, anchors, references, slugify};
 pub use crate::ast::{
-    Attribute, Block, Diagnostic, Document, Node, Position, Severity, Span, Text, TextLine,
+    Attribute, Block, Diagnostic, DiagnosticCode, Document, Edit, Fix, Inline, InlineCode,
+    InlineMarker, InlineText, Node, Position, Region, RelatedSpan, Severity, Span, Text,
+    TextChange, TextLine,
+};
+pub use crate::backend::{
+    HtmlOptions, MarkdownAnchors, MarkdownOptions, RenderContext, Renderer, Renderers,
+    heading_level, render_html, render_html_with_options, render_markdown,
+    render_markdown_with_options, section_depth,
+};
+pub use crate::format::{
+    FormatOptions, format_document, format_edits, format_parsed_edits, format_parsed_range_edits,
+    format_range_edits,
+};
+pub use crate::inline::{Escape, scan_escapes};
+pub use crate::messages::{Locale, message};
+pub use crate::outline::{OutlineEntry, outline};
+pub use crate::parser::{
+    ParseOptions, ParseResult, parse_document, parse_document_with_options, parse_syntax,
+};
+pub use crate::reparse::{reparse_document, reparse_document_with_options};
+pub use crate::schema::{
+    Args, AttrSchema, BLOCK_ATTRS, BUILTIN_BLOCKS, BlockKind, BlockSchema, CODE_LANGUAGES,
+    Children, DOCUMENT_ATTRS, ParamSchema, ParamValue, Render, lookup_block, validate_document,
+};
+pub use crate::syntax::{
+    GreenElement, GreenNode, GreenToken, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken,
 };
-pub use crate::backend::{render_html, render_markdown};
-pub use crate::parser::{ParseOptions, ParseResult, parse_document, parse_document_with_options};
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_document, render_html, render_markdown};
+    use super::{
+        Args, BUILTIN_BLOCKS, Block, BlockSchema, Children, Diagnostic, DiagnosticCode, Edit,
+        FormatOptions, HtmlOptions, Inline, Locale, MarkdownAnchors, MarkdownOptions, Node,
+        ParseOptions, ParseResult, RenderContext, Renderer, Renderers, Severity, SyntaxKind,
+        TextChange, anchors, format_document, format_edits, format_range_edits, heading_level,
+        lookup_block, message, outline, parse_document, parse_document_with_options, references,
+        render_html, render_html_with_options, render_markdown, render_markdown_with_options,
+        reparse_document, scan_escapes, section_depth, slugify, validate_document,
+    };
+
+    fn apply_edits(input: &str, edits: &[Edit]) -> String {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        let offset =
+            |line: usize, col8: usize| line_starts.get(line).map_or(input.len(), |s| s + col8);
+        let mut out = input.to_string();
+        for edit in edits.iter().rev() {
+            let start = offset(edit.span.start.line, edit.span.start.col8);
+            let end = offset(edit.span.end.line, edit.span.end.col8);
+            out.replace_range(start..end, edit.new_text.as_str());
+        }
+        out
+    }
 
     #[test]
     fn parses_block_with_attrs_and_text() {
@@ -20,7 +76,8 @@ mod tests {
             }
         "#;
         let result = parse_document(input);
-        assert_eq!(result.diagnostics.len(), 0);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].message, "unknown block '@block'");
         assert_eq!(result.document.nodes.len(), 1);
     }
 
@@ -54,9 +111,9 @@ println!("hi");
         assert_eq!(markdown, expected_markdown);
 
         let html = render_html(&parsed.document);
-        let expected_html = r#"<div class="lmm-document" data-title="Demo">
-<section class="lmm-part">
-<h1>Hello World</h1>
+        let expected_html = r##"<div class="lmm-document" data-title="Demo">
+<section class="lmm-part" id="hello-world">
+<h1><a class="lmm-anchor" href="#hello-world">Hello World</a></h1>
 <ul class="lmm-list" data-param-bullet="">
 <li>First item</li>
 <li>Second item</li>
@@ -65,7 +122,1055 @@ println!("hi");
 </code></pre>
 </section>
 </div>
-"#;
+"##;
         assert_eq!(html, expected_html);
     }
+
+    #[test]
+    fn parses_inline_markup() {
+        let input =
+            "@part Demo {\n  今天@b {非常@i {好}}，见@link(https://a.b) {预报}与``x}y``。\n}\n";
+        let parsed = parse_document(input);
+        assert_eq!(parsed.diagnostics.len(), 0);
+
+        let Node::Block(part) = &parsed.document.nodes[0] else {
+            panic!("expected part block");
+        };
+        let Node::Text(text) = &part.nodes[0] else {
+            panic!("expected text");
+        };
+        let inlines = &text.lines[0].inlines;
+        assert_eq!(inlines.len(), 7);
+        let Inline::Marker(bold) = &inlines[1] else {
+            panic!("expected bold marker");
+        };
+        assert_eq!(bold.name, "b");
+        assert_eq!(bold.span.start.col32, 4);
+        assert_eq!(bold.span.end.col32, 17);
+        assert!(matches!(&bold.nodes[1], Inline::Marker(inner) if inner.name == "i"));
+        let Inline::Marker(link) = &inlines[3] else {
+            panic!("expected link marker");
+        };
+        assert_eq!(link.arg.as_deref(), Some("https://a.b"));
+        assert!(matches!(&inlines[5], Inline::Code(code) if code.value == "x}y"));
+    }
+
+    #[test]
+    fn reports_unclosed_inline_group() {
+        let parsed = parse_document("text @b {bold\n");
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(parsed.diagnostics[0].severity, Severity::Error);
+        assert_eq!(parsed.diagnostics[0].span.start.col8, 5);
+    }
+
+    #[test]
+    fn renders_inline_markup() {
+        let input = "@list[bullet] {\n  @b {粗} @i {斜} @u {下} ``a<b`` @link(https://x.y/?a=1&b=2) {站}\n  @link(JavaScript:alert(1)) {坏}\n}\n";
+        let parsed = parse_document(input);
+        assert_eq!(parsed.diagnostics.len(), 0);
+
+        let markdown = render_markdown(&parsed.document);
+        let expected_markdown = "- **粗** *斜* <u>下</u> `a<b` [站](https://x.y/?a=1&b=2)\n- 坏";
+        assert_eq!(markdown, expected_markdown);
+
+        let html = render_html(&parsed.document);
+        assert!(html.contains(
+            "<li><strong>粗</strong> <em>斜</em> <u>下</u> <code>a&lt;b</code> \
+             <a href=\"https://x.y/?a=1&amp;b=2\">站</a></li>"
+        ));
+        assert!(html.contains("<li>坏</li>"));
+
+        let parsed = parse_document("a*b_c [x] `y` <i>hi</i> \\ @link(https://x.y/a b<c>) {t*}\n");
+        assert_eq!(
+            render_markdown(&parsed.document),
+            "a\\*b\\_c \\[x\\] \\`y\\` \\<i\\>hi\\</i\\> \\\\ [t\\*](https://x.y/a%20b%3Cc%3E)"
+        );
+
+        // Text that Markdown would read as a heading, list, quote or entity.
+        let parsed = parse_document("##y #\n- a\n12. b & c\n@list {\n  + d\n  3) e\n}\n");
+        assert_eq!(
+            render_markdown(&parsed.document),
+            "\\#y #\n\\- a\n12\\. b \\& c\n\n- \\+ d\n- 3\\) e"
+        );
+    }
+
+    #[test]
+    fn renders_blocks_with_registered_handlers() {
+        struct Warning;
+        impl Renderer for Warning {
+            fn html(&self, block: &Block, cx: &mut RenderContext<'_>) {
+                cx.push_str("<aside class=\"warning\"");
+                cx.push_html_attrs(block);
+                cx.push_str(">\n");
+                cx.render_nodes(&block.nodes);
+                cx.push_str("</aside>\n");
+            }
+
+            fn markdown(&self, block: &Block, cx: &mut RenderContext<'_>) {
+                cx.push_str("> **Warning:** ");
+                cx.push_text(&block.args.join(" "));
+                cx.push_str("\n\n");
+                cx.render_nodes(&block.nodes);
+            }
+        }
+        struct Code;
+        impl Renderer for Code {
+            fn html(&self, block: &Block, cx: &mut RenderContext<'_>) {
+                cx.push_str("<figure>\n");
+                cx.render_builtin(block);
+                cx.push_str("</figure>\n");
+            }
+        }
+
+        let input = "@warning Hot & <sharp> {\n  #level: 2\n  @part Inside {\n    @b {x}\n  }\n  @code {\n    y\n  }\n}\n";
+        let parsed = parse_document(input);
+        let mut renderers = Renderers::new();
+        renderers.register("warning", Warning);
+        renderers.register("code", Code);
+
+        let markdown = renderers.render_markdown(&parsed.document);
+        assert_eq!(
+            markdown,
+            "> **Warning:** Hot \\& \\<sharp\\>\n\n# Inside\n\n**x**\n\n```\ny\n```"
+        );
+        let html = renderers.render_html(&parsed.document);
+        assert!(html.contains("<aside class=\"warning\" data-level=\"2\">\n<section"));
+        assert!(html.contains(">Inside</a></h1>\n<p><strong>x</strong></p>"));
+        assert!(
+            html.contains("<figure>\n<pre class=\"lmm-code\"><code>y\n</code></pre>\n</figure>")
+        );
+
+        let plain = render_markdown(&parsed.document);
+        assert!(plain.starts_with("# Inside"));
+    }
+
+    #[test]
+    fn renders_html_with_options() {
+        let input = "#title: A & B\n#lang: en\n@part Top {\n  #id: x\n  @code[lang=rs] {\n    a\n  }\n  @b {y} @u {z}\n}\n";
+        let parsed = parse_document(input);
+
+        let fragment = HtmlOptions {
+            wrapper: false,
+            class_prefix: "doc-".into(),
+            data_params: false,
+            heading_offset: 1,
+            heading_links: false,
+            pretty: false,
+            ..HtmlOptions::default()
+        };
+        assert_eq!(
+            render_html_with_options(&parsed.document, &fragment),
+            "<section class=\"doc-part\" id=\"x\"><h2>Top</h2>\
+             <pre class=\"doc-code\"><code class=\"language-rs\">a\n</code></pre>\
+             <p><strong>y</strong> <u>z</u></p></section>"
+        );
+
+        let page = HtmlOptions {
+            standalone: true,
+            data_attrs: false,
+            stylesheets: vec!["style.css".into()],
+            scripts: vec!["app.js?a=1&b=2".into()],
+            ..HtmlOptions::default()
+        };
+        let html = render_html_with_options(&parsed.document, &page);
+        assert!(html.starts_with(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>A &amp; B</title>\n<link rel=\"stylesheet\" href=\"style.css\">\n\
+             </head>\n<body>\n<div class=\"lmm-document\">\n<section class=\"lmm-part\" id=\"x\">\n<h1>"
+        ));
+        assert!(html.contains("<pre class=\"lmm-code\" data-param-lang=\"rs\">"));
+        assert!(
+            html.ends_with(
+                "</div>\n<script src=\"app.js?a=1&amp;b=2\"></script>\n</body>\n</html>\n"
+            )
+        );
+        assert_eq!(
+            render_html_with_options(&parsed.document, &HtmlOptions::default()),
+            render_html(&parsed.document)
+        );
+    }
+
+    #[test]
+    fn anchors_sections_with_ids_and_slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("块 属性：示例"), "块-属性-示例");
+        assert_eq!(slugify("Ünïcode_ok 2"), "ünïcode_ok-2");
+        assert_eq!(slugify("!!!"), "section");
+
+        let input = "@part Intro {\n}\n@part Intro {\n}\n@part Other {\n  #id: intro-1\n}\n@part 转义 处理 {\n  @code {\n    @part Hidden {\n  }\n}\n@node {\n  #id: box\n}\n";
+        let parsed = parse_document(input);
+        let html = render_html(&parsed.document);
+        let ids: Vec<_> = html
+            .match_indices(" id=\"")
+            .map(|(idx, _)| {
+                let rest = &html[idx + 5..];
+                &rest[..rest.find('"').unwrap()]
+            })
+            .collect();
+        assert_eq!(ids, ["intro", "intro-2", "intro-1", "转义-处理", "box"]);
+        assert!(html.contains("<h1><a class=\"lmm-anchor\" href=\"#intro-2\">Intro</a></h1>"));
+        assert!(html.contains("<div class=\"lmm-block lmm-block-node\" id=\"box\">"));
+        assert!(!html.contains("data-id"));
+
+        let attribute = MarkdownOptions {
+            anchors: MarkdownAnchors::Attribute,
+        };
+        let markdown = render_markdown_with_options(&parsed.document, attribute);
+        assert!(
+            markdown.starts_with("# Intro {#intro}\n\n# Intro {#intro-2}\n\n# Other {#intro-1}")
+        );
+        // Ids that would break the attribute are left out.
+        let odd = parse_document("@part A {\n  #id: a b}\n}\n@part B {\n  #id: b.1:x\n}\n");
+        let markdown = render_markdown_with_options(&odd.document, attribute);
+        assert_eq!(markdown, "# A\n\n# B {#b.1:x}");
+        let tagged = MarkdownOptions {
+            anchors: MarkdownAnchors::Html,
+        };
+        let markdown = render_markdown_with_options(&parsed.document, tagged);
+        assert!(markdown.contains("<a id=\"转义-处理\"></a>\n\n# 转义 处理\n"));
+        assert!(render_markdown(&parsed.document).starts_with("# Intro\n\n# Intro\n"));
+
+        // Handlers that rebuild a block keep its anchor.
+        struct Rebuilt;
+        impl Renderer for Rebuilt {
+            fn html(&self, block: &Block, cx: &mut RenderContext<'_>) {
+                let mut copy = block.clone();
+                copy.args.push("(copy)".into());
+                cx.render_builtin(&copy);
+            }
+        }
+        let mut renderers = Renderers::new();
+        renderers.register("part", Rebuilt);
+        let html = renderers.render_html(&parsed.document);
+        assert!(html.contains("<section class=\"lmm-part\" id=\"intro-2\">"));
+        assert!(html.contains("<a class=\"lmm-anchor\" href=\"#intro-1\">Other (copy)</a>"));
+    }
+
+    #[test]
+    fn counts_heading_levels_like_the_renderers() {
+        let input =
+            "@part a {\n  @list {\n    @part b {\n      @part c {\n      }\n    }\n  }\n}\n";
+        let parsed = parse_document(input);
+        let Node::Block(a) = &parsed.document.nodes[0] else {
+            panic!("expected part");
+        };
+        let Node::Block(list) = &a.nodes[0] else {
+            panic!("expected list");
+        };
+        let Node::Block(b) = &list.nodes[0] else {
+            panic!("expected part");
+        };
+        assert_eq!(section_depth(&[a]), 1);
+        assert_eq!(section_depth(&[a, list, b]), 1);
+        assert_eq!(heading_level(section_depth(&[a, list, b]), 0), 2);
+        assert_eq!(heading_level(5, 3), 6);
+        let html = render_html(&parsed.document);
+        assert!(html.contains(">b</a></h1>") && html.contains(">c</a></h2>"));
+    }
+
+    #[test]
+    fn resolves_cross_references() {
+        let input = "@part Intro {\n  #id: intro\n  See @ref(later-part) {} and @ref(intro) {this part}.\n}\n@part Later Part {\n  @ref(missing) {x}\n}\n@node {\n  #id: intro\n}\n";
+        let parsed = parse_document(input);
+        let found: Vec<_> = parsed
+            .diagnostics
+            .iter()
+            .map(|diag| (diag.span.start.line, diag.code, diag.related.len()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (5, DiagnosticCode::DanglingReference, 0),
+                (8, DiagnosticCode::DuplicateId, 1),
+            ]
+        );
+        let ids: Vec<_> = anchors(&parsed.document)
+            .into_iter()
+            .map(|anchor| (anchor.id, anchor.explicit))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                ("intro".into(), true),
+                ("later-part".into(), false),
+                ("intro".into(), true)
+            ]
+        );
+        let targets: Vec<_> = references(&parsed.document)
+            .into_iter()
+            .map(|reference| reference.id)
+            .collect();
+        assert_eq!(targets, ["later-part", "intro", "missing"]);
+
+        let html = render_html(&parsed.document);
+        assert!(html.contains(
+            "See <a class=\"lmm-ref\" href=\"#later-part\">Later Part</a> and \
+             <a class=\"lmm-ref\" href=\"#intro\">this part</a>."
+        ));
+        assert!(html.contains("<p>x</p>"));
+        let markdown = render_markdown(&parsed.document);
+        assert!(markdown.contains("See [Later Part](#later-part) and [this part](#intro)."));
+
+        // Fixing the reference elsewhere clears the diagnostic after an incremental reparse.
+        let start = input.find("missing").unwrap();
+        let text = input.replacen("missing", "intro", 1);
+        let change = TextChange::new(start, start + "missing".len(), "intro".len());
+        let result = reparse_document(parsed, &text, change);
+        assert_eq!(result.diagnostics, parse_document(&text).diagnostics);
+        assert_eq!(result.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn renders_table_of_contents() {
+        let input = "@toc[depth=2] {\n}\n@part Intro {\n  @part Details {\n    @part Deep {\n      x\n    }\n  }\n}\n@node {\n  @part Other {\n    #id: other-part\n  }\n}\n";
+        let parsed = parse_document(input);
+        assert!(parsed.diagnostics.is_empty());
+        let entries = outline(&parsed.document);
+        let flat: Vec<_> = entries
+            .iter()
+            .flat_map(|entry| std::iter::once(entry).chain(&entry.children))
+            .map(|entry| (entry.title.as_str(), entry.id.as_str(), entry.level))
+            .collect();
+        assert_eq!(
+            flat,
+            [
+                ("Intro", "intro", 1),
+                ("Details", "details", 2),
+                ("Other", "other-part", 1)
+            ]
+        );
+        assert_eq!(entries[0].children[0].children[0].title, "Deep");
+        let intro = &entries[0];
+        assert_eq!((intro.span.start.line, intro.span.end.line), (2, 8));
+        assert_eq!(intro.header_span.end.line, 2);
+
+        let markdown = render_markdown(&parsed.document);
+        assert!(markdown.starts_with(
+            "- [Intro](#intro)\n  - [Details](#details)\n- [Other](#other-part)\n\n# Intro"
+        ));
+        let html = render_html(&parsed.document);
+        assert!(html.contains(
+            "<nav class=\"lmm-toc\" data-param-depth=\"2\">\n<ul>\n\
+             <li><a href=\"#intro\">Intro</a>\n<ul>\n\
+             <li><a href=\"#details\">Details</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#other-part\">Other</a></li>\n</ul>\n</nav>\n"
+        ));
+
+        // A part inside a list renders as a top-level heading, so it follows
+        // the enclosing part in the outline, as do the parts after it.
+        let input = "@toc[depth=1] {\n}\n@part Outer {\n  @list {\n    @part Listed {\n      @part Inner {\n      }\n    }\n  }\n  @part Later {\n  }\n}\n";
+        let parsed = parse_document(input);
+        let entries = outline(&parsed.document);
+        let tree: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                let children: Vec<_> = entry.children.iter().map(|child| child.level).collect();
+                (entry.title.as_str(), entry.level, children)
+            })
+            .collect();
+        assert_eq!(tree, [("Outer", 1, vec![]), ("Listed", 1, vec![2, 2])]);
+        let markdown = render_markdown(&parsed.document);
+        assert!(markdown.starts_with("- [Outer](#outer)\n- [Listed](#listed)\n\n# Outer"));
+        assert!(markdown.contains("# Outer\n\n# Listed\n\n## Inner\n\n## Later"));
+    }
+
+    #[test]
+    fn formats_document_canonically() {
+        let input = "#title: x\n@part A{\ntext\n    @list[bullet]{\n one\n  }\n      @code[lang=py] ++{\n          if x:\n              y()   \n      }++\n$\n   keep   this\n$\n! note\n}\n\n\n";
+        let expected = "#title: x\n@part A {\n  text\n  @list[bullet] {\n    one\n  }\n  @code[lang=py] ++{\n    if x:\n        y()   \n  }++\n  $\n   keep   this\n  $\n  ! note\n}\n";
+        let formatted = format_document(input, FormatOptions::default());
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format_document(&formatted, FormatOptions::default()),
+            formatted
+        );
+
+        let tabs = FormatOptions {
+            use_tabs: true,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            format_document("@part A {\n@part B {\nx\n}\n}", tabs),
+            "@part A {\n\t@part B {\n\t\tx\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn formats_around_raw_content() {
+        let options = FormatOptions::default();
+        // A `$` line inside a code block does not open a region.
+        let input = "@code[lang=sh] {\n  $\n  echo hi   \n}\n@part x{\n    y\n}\n";
+        assert_eq!(
+            format_document(input, options),
+            "@code[lang=sh] {\n  $\n  echo hi   \n}\n@part x {\n  y\n}\n"
+        );
+
+        let malformed = [
+            "@code[lang=py] ++{\n  if x:\n    y\n@part a {++\n  z\n}\n",
+            "@node {\n  #id: a\n @code { text\n @part a { v\n}\n",
+            "@xrt A\n}\n@part B {\n$\nfree\n@code ++!\n  if x:\n}++\n@node {\n  @toc {: v\n}\n",
+            "@part a {\n  $\n    @b {x\n  @part b {\n}\n",
+        ];
+        for input in malformed {
+            let once = format_document(input, options);
+            assert_eq!(format_document(&once, options), once, "{input:?}");
+            assert_eq!(apply_edits(input, &format_edits(input, options)), once);
+        }
+
+        // Blank space after a `{` is dropped without turning the attribute
+        // lines below it into text, or text into attributes.
+        let inputs = [
+            ("@part A { \n#id: a\n}\n", "@part A {\n  #id: a\n}\n"),
+            (
+                "@x@list[bullet] { \n#id: a  ",
+                "@x@list[bullet] {\n  #id: a\n",
+            ),
+        ];
+        for (input, expected) in inputs {
+            let once = format_document(input, options);
+            assert_eq!(once, expected);
+            assert_eq!(format_document(&once, options), once);
+            let attrs = |text: &str| {
+                let parsed = parse_document(text);
+                let Some(Node::Block(block)) = parsed.document.nodes.last() else {
+                    panic!("expected a block");
+                };
+                let block = match block.nodes.last() {
+                    Some(Node::Block(inner)) => inner,
+                    _ => block,
+                };
+                block.attrs.len()
+            };
+            assert_eq!((attrs(input), attrs(&once)), (1, 1));
+        }
+    }
+
+    #[test]
+    fn format_edits_are_minimal() {
+        let input = "@part 标题{\n      正文\n  }\n\n\n";
+        let edits = format_edits(input, FormatOptions::default());
+        assert_eq!(
+            apply_edits(input, &edits),
+            format_document(input, FormatOptions::default())
+        );
+        assert_eq!(edits.len(), 4);
+        assert_eq!(edits[0].new_text, " ");
+        assert_eq!(edits[0].span.start.col32, 8);
+        assert_eq!(edits[1].new_text, "");
+        assert_eq!(edits[1].span.end.col8, 6);
+
+        let range = format_range_edits(input, 1, 1, FormatOptions::default());
+        assert_eq!(range.len(), 1);
+        assert_eq!(range[0].span.start.line, 1);
+
+        // Each line keeps its own line ending.
+        let options = FormatOptions::default();
+        let inputs = [
+            "@part A{\r\ntext\n}\r\n",
+            "@part A{\ntext\r\n}",
+            "@node {\r\n  x\n}\n\r\n\n",
+        ];
+        let expected = [
+            "@part A {\r\n  text\n}\r\n",
+            "@part A {\n  text\r\n}\r\n",
+            "@node {\r\n  x\n}\n",
+        ];
+        for (input, expected) in inputs.into_iter().zip(expected) {
+            assert_eq!(format_document(input, options), expected);
+            assert_eq!(apply_edits(input, &format_edits(input, options)), expected);
+        }
+    }
+
+    #[test]
+    fn syntax_tree_is_lossless() {
+        let inputs = [
+            "#title: Demo\r\n@part A {\r\n  text @b {x}\r\n}\r\n",
+            "@code[lang=rs] ++{\n  fn f() {}\n}++ tail\n",
+            "$\n  @part raw {\n$\n! note\n!!bang\n",
+            "@part missing\n@ {\nmore } after\n}\n",
+            "\t@list[bullet]{\n\t\tone\n",
+        ];
+        for input in inputs {
+            let result = parse_document(input);
+            assert_eq!(result.syntax.text(), input);
+            assert_eq!(result.syntax.kind(), SyntaxKind::Document);
+        }
+
+        let result = parse_document("#k: v\n@part A {\n  x\n}\n");
+        let kinds: Vec<_> = result
+            .syntax
+            .child_nodes()
+            .map(|node| node.kind())
+            .collect();
+        assert_eq!(kinds, [SyntaxKind::AttrLine, SyntaxKind::Block]);
+        let block = result.syntax.first_child_node(SyntaxKind::Block).unwrap();
+        assert_eq!(block.text(), "@part A {\n  x\n}");
+        assert!(block.first_child_node(SyntaxKind::Header).is_some());
+        assert_eq!(
+            block.first_child_token(SyntaxKind::Close).unwrap().text(),
+            "}"
+        );
+    }
+
+    #[test]
+    fn reparse_matches_full_parse() {
+        let mut text = String::from("#title: x\n@part A {\n  one\n}\n\n@part B {\n  two\n}\n");
+        let mut result = parse_document(&text);
+        let edits = [
+            ("  two\n", "  two @b {bold}\n  more\n"),
+            ("@part B {", "@part B{"),
+            ("  more\n}", "  more\n"),
+            ("  more\n", "  more\n}\n$\n"),
+            ("$\n", ""),
+        ];
+        for (old, new) in edits {
+            let start = text.find(old).unwrap();
+            let change = TextChange::new(start, start + old.len(), new.len());
+            text.replace_range(start..start + old.len(), new);
+            let previous = result.clone();
+            result = reparse_document(result, &text, change);
+            let full = parse_document(&text);
+            assert_eq!(result.syntax, full.syntax);
+            assert_eq!(result.document, full.document);
+            let mut diagnostics = result.diagnostics.clone();
+            let mut expected = full.diagnostics.clone();
+            diagnostics.sort();
+            expected.sort();
+            assert_eq!(diagnostics, expected);
+
+            let first =
+                |result: &ParseResult| result.syntax.first_child_node(SyntaxKind::Block).unwrap();
+            assert!(std::sync::Arc::ptr_eq(
+                first(&previous).green(),
+                first(&result).green()
+            ));
+        }
+    }
+
+    #[test]
+    fn scans_escapes() {
+        let escapes = scan_escapes("  !!a @@b ##c {{d", true);
+        let found: Vec<_> = escapes
+            .iter()
+            .map(|escape| (escape.start, escape.end, escape.value.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [(2, 4, "!"), (6, 8, "@"), (10, 12, "#"), (14, 16, "{")]
+        );
+        assert!(scan_escapes("!!a", false).is_empty());
+    }
+
+    #[test]
+    fn resolves_escape_runs() {
+        let text = |input: &str| {
+            let parsed = parse_document(input);
+            let Node::Text(text) = &parsed.document.nodes[0] else {
+                panic!("expected text");
+            };
+            text.lines[0].value.to_string()
+        };
+        assert_eq!(text("{{a"), "{a");
+        assert_eq!(text("{{{a"), "{{a");
+        assert_eq!(text("{{{{a"), "{{{a");
+        assert_eq!(text("a @@b @@@c"), "a @b @@c");
+        assert_eq!(text("##a ###b"), "#a ##b");
+        assert_eq!(text("!!a"), "!a");
+        assert_eq!(text("!!!a"), "!!a");
+        assert_eq!(text("a !!b"), "a !!b");
+        assert_eq!(text("a }} b }}} c"), "a } b }} c");
+
+        let parsed = parse_document("@part p {\n  x }} y\n  @b {z}}} w\n}\n");
+        assert!(parsed.diagnostics.is_empty());
+        let Node::Block(part) = &parsed.document.nodes[0] else {
+            panic!("expected block");
+        };
+        let Node::Text(body) = &part.nodes[0] else {
+            panic!("expected text");
+        };
+        assert_eq!(body.lines[0].value, "x } y");
+        assert_eq!(body.lines[1].value, "@b {z}} w");
+        assert!(matches!(
+            &body.lines[1].inlines[..],
+            [Inline::Marker(_), Inline::Text(text)] if text.value == "} w"
+        ));
+    }
+
+    #[test]
+    fn keeps_code_content_verbatim() {
+        let body = "d = {{}}\nx = \"@@\" ## c\n!! not comment\n@@x {{y}} }}";
+        let indented = body.replace('\n', "\n  ");
+        let input = format!("@code ++{{\n  {indented}\n}}++\n");
+        let parsed = parse_document(&input);
+        assert!(parsed.diagnostics.is_empty());
+        let markdown = render_markdown(&parsed.document);
+        assert_eq!(markdown, format!("```\n{body}\n```"));
+        let html = render_html(&parsed.document);
+        assert!(html.contains(
+            "<code>d = {{}}\nx = &quot;@@&quot; ## c\n!! not comment\n@@x {{y}} }}\n</code>"
+        ));
+    }
+
+    #[test]
+    fn unescapes_args_and_attribute_values() {
+        let parsed = parse_document("#title: a ## b {{c}}\n@code x{{y [lang=@@rs] {\n}\n");
+        assert_eq!(parsed.document.attrs[0].value, "a # b {c}");
+        let Node::Block(code) = &parsed.document.nodes[0] else {
+            panic!("expected block");
+        };
+        assert_eq!(code.args, ["x{y"]);
+        assert_eq!(code.params[0].value, "@rs");
+    }
+
+    #[test]
+    fn validates_blocks_against_schemas() {
+        let input = "@list[bullet,line] {\n  @part x {\n  }\n}\n@code[lang=rust,tabs] {\n  @anything {\n  }\n}\n@widget {\n  see @link {here} @b(x) {y}\n}\n";
+        let parsed = parse_document(input);
+        let found: Vec<_> = parsed
+            .diagnostics
+            .iter()
+            .map(|diag| (diag.span.start.line, &diag.severity, diag.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    0,
+                    &Severity::Error,
+                    "params 'bullet' and 'line' of '@list' cannot be used together"
+                ),
+                (4, &Severity::Warning, "unknown param 'tabs' for '@code'"),
+                (8, &Severity::Warning, "unknown block '@widget'"),
+                (
+                    9,
+                    &Severity::Warning,
+                    "'@link' needs an argument, as in '@link(...)'"
+                ),
+                (9, &Severity::Warning, "'@b' does not take arguments"),
+            ]
+        );
+
+        let options = ParseOptions {
+            validate: false,
+            ..ParseOptions::default()
+        };
+        let parsed = parse_document_with_options("@widget {\n}\n", options);
+        assert!(parsed.diagnostics.is_empty());
+        let widget = BlockSchema {
+            name: "widget",
+            ..*lookup_block("node").unwrap()
+        };
+        let schemas = [BUILTIN_BLOCKS, &[widget]].concat();
+        assert!(validate_document(&parsed.document, &schemas, Locale::En).is_empty());
+        assert_eq!(
+            validate_document(&parsed.document, BUILTIN_BLOCKS, Locale::En).len(),
+            1
+        );
+
+        let figure = BlockSchema {
+            name: "figure",
+            args: Args::Required,
+            children: Children::Only(&["code"]),
+            ..*lookup_block("node").unwrap()
+        };
+        let parsed = parse_document_with_options(
+            "@figure {\n  @part x {\n  }\n}\n@figure Caption {\n  @code {\n  }\n}\n",
+            options,
+        );
+        let schemas = [BUILTIN_BLOCKS, &[figure]].concat();
+        let found: Vec<_> = validate_document(&parsed.document, &schemas, Locale::En)
+            .into_iter()
+            .map(|diag| (diag.span.start.line, diag.code, diag.message))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    0,
+                    DiagnosticCode::MissingArgument,
+                    "'@figure' needs an argument, as in '@figure ... {'".into()
+                ),
+                (
+                    1,
+                    DiagnosticCode::DisallowedChild,
+                    "'@part' is not allowed inside '@figure'".into()
+                ),
+            ]
+        );
+
+        // Cross-references are checked once, when parsing.
+        let parsed = parse_document("@figure Caption {\n  @ref(nowhere) {x}\n}\n");
+        let dangling = |diagnostics: &[Diagnostic]| {
+            diagnostics
+                .iter()
+                .filter(|diag| diag.code == DiagnosticCode::DanglingReference)
+                .count()
+        };
+        assert_eq!(dangling(&parsed.diagnostics), 1);
+        let revalidated = validate_document(&parsed.document, &schemas, Locale::En);
+        assert_eq!(dangling(&revalidated), 0);
+    }
+
+    #[test]
+    fn dedents_code_blocks_and_checks_indentation() {
+        let input = "@part a {\n  @code[lang=python] ++{\n    def f():\n      @node {\n    }\n   x\n  \t y\n  }++\n}\n";
+        let parsed = parse_document(input);
+        let Node::Block(part) = &parsed.document.nodes[0] else {
+            panic!("expected part");
+        };
+        let Node::Block(code) = &part.nodes[0] else {
+            panic!("expected code");
+        };
+        assert_eq!(code.nodes.len(), 1);
+        let Node::Text(text) = &code.nodes[0] else {
+            panic!("code content should stay text");
+        };
+        let lines: Vec<_> = text
+            .lines
+            .iter()
+            .map(|line| (line.indent, line.value.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (0, "def f():"),
+                (2, "@node {"),
+                (0, "}"),
+                (0, "x"),
+                (1, "y")
+            ]
+        );
+
+        let found: Vec<_> = parsed
+            .diagnostics
+            .iter()
+            .map(|diag| (diag.span.start.line, diag.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    5,
+                    "code line is indented less than the first line of its block"
+                ),
+                (6, "code line mixes tabs and spaces in its indentation"),
+            ]
+        );
+
+        let parsed = parse_document("@code {\nx\n}\n");
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(
+            parsed.diagnostics[0].message,
+            "code block content should be indented past its header"
+        );
+    }
+
+    #[test]
+    fn renders_code_with_relative_indentation() {
+        let input = "@code[lang=python] {\n\n    def f():\n        if x:\n\n            return 1\n\n    f()\n\n}\n";
+        let parsed = parse_document(input);
+        assert!(parsed.diagnostics.is_empty());
+
+        let markdown = render_markdown(&parsed.document);
+        assert_eq!(
+            markdown,
+            "```python\ndef f():\n    if x:\n\n        return 1\n\nf()\n```"
+        );
+        let html = render_html(&parsed.document);
+        assert!(html.contains(
+            "<code class=\"language-python\">def f():\n    if x:\n\n        return 1\n\nf()\n</code>"
+        ));
+    }
+
+    #[test]
+    fn groups_lines_into_paragraphs() {
+        let input = "one\ntwo @b {2}\n\n! hidden\n\n@part p {\n    three\n\n\n    four\n}\n";
+        let parsed = parse_document(input);
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.document.nodes.len(), 3);
+        let Node::Block(part) = &parsed.document.nodes[2] else {
+            panic!("expected part");
+        };
+        assert_eq!(part.nodes.len(), 2);
+
+        let markdown = render_markdown(&parsed.document);
+        assert_eq!(markdown, "one\ntwo **2**\n\n# p\n\nthree\n\nfour");
+        let html = render_html(&parsed.document);
+        assert!(html.contains("<p>one\ntwo <strong>2</strong></p>\n<section"));
+        assert!(html.contains("<p>three</p>\n<p>four</p>\n"));
+    }
+
+    #[test]
+    fn diagnostics_carry_codes_related_spans_and_fixes() {
+        let input = "@part{\n  text @b {x\n";
+        let parsed = parse_document(input);
+        let codes: Vec<_> = parsed
+            .diagnostics
+            .iter()
+            .map(|diag| diag.code.as_str())
+            .collect();
+        assert_eq!(codes, ["LMM002", "LMM001", "LMM003"]);
+        assert_eq!(DiagnosticCode::UnclosedBlock.name(), "unclosed-block");
+
+        let unclosed = &parsed.diagnostics[0];
+        assert_eq!(unclosed.span.start.line, 0);
+        assert_eq!(unclosed.span.end.col8, 6);
+        assert!(unclosed.related.is_empty());
+
+        let mut edits: Vec<Edit> = parsed
+            .diagnostics
+            .iter()
+            .flat_map(|diag| diag.fixes[0].edits.clone())
+            .collect();
+        edits.sort_by_key(|edit| (edit.span.start.line, edit.span.start.col8));
+        let fixed = apply_edits(input, &edits);
+        assert_eq!(fixed, "@part {\n  text @b {x}\n}\n");
+        assert!(parse_document(&fixed).diagnostics.is_empty());
+    }
+
+    #[test]
+    fn localizes_diagnostic_messages() {
+        for code in DiagnosticCode::ALL {
+            for locale in [Locale::En, Locale::ZhCn] {
+                assert_ne!(message(locale, *code, "", &[]), code.as_str());
+            }
+        }
+        assert_eq!(Locale::from_tag("zh-Hans-CN"), Some(Locale::ZhCn));
+        assert_eq!(Locale::from_tag("en-US"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("fr"), None);
+
+        let input = "@part{\n  @x {\n  }\n";
+        let english = parse_document(input);
+        let options = ParseOptions {
+            locale: Locale::ZhCn,
+            ..ParseOptions::default()
+        };
+        let chinese = parse_document_with_options(input, options);
+        let messages = |parsed: &ParseResult| {
+            parsed
+                .diagnostics
+                .iter()
+                .map(|diag| (diag.code, diag.message.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages(&english),
+            [
+                (
+                    DiagnosticCode::UnclosedBlock,
+                    "unclosed block, expected '}'".to_string()
+                ),
+                (
+                    DiagnosticCode::MissingSpace,
+                    "missing space between block name and '{'".to_string()
+                ),
+                (
+                    DiagnosticCode::UnknownBlock,
+                    "unknown block '@x'".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            messages(&chinese),
+            [
+                (
+                    DiagnosticCode::UnclosedBlock,
+                    "块未闭合，缺少 '}'".to_string()
+                ),
+                (
+                    DiagnosticCode::MissingSpace,
+                    "标记名称与左大括号之间缺少空格".to_string()
+                ),
+                (DiagnosticCode::UnknownBlock, "未知的块 '@x'".to_string()),
+            ]
+        );
+        assert_eq!(chinese.diagnostics[0].fixes[0].title, "插入 '}'");
+    }
+
+    #[test]
+    fn reports_unclosed_blocks_at_their_header() {
+        let input = "@part a {
+  @code ++{
+    x }
+  }+
+}
+";
+        let parsed = parse_document(input);
+        let unclosed: Vec<_> = parsed
+            .diagnostics
+            .iter()
+            .filter(|diag| diag.code == DiagnosticCode::UnclosedBlock)
+            .collect();
+        assert_eq!(unclosed.len(), 2);
+        let code = unclosed[1];
+        assert_eq!(code.message, "unclosed block, expected '}++'");
+        assert_eq!(code.span.start.line, 1);
+        assert_eq!((code.span.start.col8, code.span.end.col8), (2, 11));
+        assert_eq!(code.related.len(), 1);
+        let note = &code.related[0];
+        assert_eq!(
+            note.message,
+            "'}+' does not close this block, which needs '}++'"
+        );
+        assert_eq!(note.span.start.line, 3);
+        assert_eq!((note.span.start.col8, note.span.end.col8), (2, 4));
+
+        let parsed = parse_document(
+            "@part {
+  @b {x}
+  text
+",
+        );
+        assert_eq!(parsed.diagnostics.len(), 1);
+        assert_eq!(
+            parsed.diagnostics[0].message,
+            "unclosed block, expected '}'"
+        );
+        assert!(parsed.diagnostics[0].related.is_empty());
+    }
+
+    /// Summarizes the block structure of `nodes`, with `t` for each paragraph
+    /// and `$` for regions.
+    fn shape(nodes: &[Node]) -> String {
+        nodes
+            .iter()
+            .map(|node| match node {
+                Node::Block(block) => format!("{}{{{}}}", block.name, shape(&block.nodes)),
+                Node::Text(_) => "t".to_string(),
+                Node::Region(region) => format!("${{{}}}", shape(&region.nodes)),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn recovers_from_unclosed_blocks_at_outer_headers() {
+        let input = "@part a {\n  text\n  @list {\n    x\n  more\n}\n@part b {\n  y\n}\n";
+        let parsed = parse_document(input);
+        assert_eq!(shape(&parsed.document.nodes), "part{t list{t}} part{t}");
+        assert_eq!(parsed.diagnostics.len(), 1);
+        let unclosed = &parsed.diagnostics[0];
+        assert_eq!(unclosed.code, DiagnosticCode::UnclosedBlock);
+        assert_eq!(unclosed.span.start.line, 0);
+        let fixed = apply_edits(input, &unclosed.fixes[0].edits);
+        assert!(fixed.contains("  more\n}\n}\n@part b {"));
+        let reparsed = parse_document(&fixed);
+        assert!(reparsed.diagnostics.is_empty());
+        assert_eq!(shape(&reparsed.document.nodes), "part{t list{t}} part{t}");
+
+        let parsed =
+            parse_document("@part a {\n  @code ++{\n    fn main() {}\n  }+\n@part b {\n  y\n}\n");
+        assert_eq!(shape(&parsed.document.nodes), "part{code{t}} part{t}");
+        let unclosed: Vec<_> = parsed
+            .diagnostics
+            .iter()
+            .filter(|diag| diag.code == DiagnosticCode::UnclosedBlock)
+            .map(|diag| (diag.span.start.line, diag.related.len()))
+            .collect();
+        assert_eq!(unclosed, [(0, 0), (1, 1)]);
+
+        let parsed = parse_document("@part a {\n  one\n@part b {\n  @list {\n    x\n  }\n}\n");
+        assert_eq!(shape(&parsed.document.nodes), "part{t} part{list{t}}");
+        assert_eq!(parsed.diagnostics.len(), 1);
+
+        let parsed = parse_document("@part a {\n  @node {\n    x\n  @node {\n    y\n  }\n}\n");
+        assert_eq!(shape(&parsed.document.nodes), "part{node{t} node{t}}");
+        assert_eq!(parsed.diagnostics[0].span.start.line, 1);
+
+        // Unindented nesting is fine as long as every block is closed.
+        let parsed = parse_document("@part a {\n@part b {\nx\n}\n}\n");
+        assert_eq!(shape(&parsed.document.nodes), "part{part{t}}");
+        assert!(parsed.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn parses_dollar_regions() {
+        let input = "@part a {\n$\nfree text\n\nmore\n$\n  after\n}\n";
+        let parsed = parse_document(input);
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(shape(&parsed.document.nodes), "part{${t t} t}");
+        let Node::Block(part) = &parsed.document.nodes[0] else {
+            panic!("expected a block");
+        };
+        let Node::Region(region) = &part.nodes[0] else {
+            panic!("expected a region");
+        };
+        assert_eq!((region.span.start.line, region.span.end.col8), (1, 1));
+        assert_eq!(region.close_span.map(|span| span.start.line), Some(5));
+        assert_eq!(
+            render_markdown(&parsed.document),
+            "# a\n\nfree text\n\nmore\n\nafter"
+        );
+
+        let parsed = parse_document("@list {\n$\nx\ny\n$\n}\n");
+        assert!(render_html(&parsed.document).contains("<li>x</li>\n<li>y</li>\n</ul>"));
+
+        let input = "@part a {\n  $ oops\n}\n";
+        let parsed = parse_document(input);
+        assert_eq!(shape(&parsed.document.nodes), "part{t}");
+        assert_eq!(parsed.diagnostics.len(), 1);
+        let diag = &parsed.diagnostics[0];
+        assert_eq!(diag.code, DiagnosticCode::DollarContent);
+        assert_eq!(diag.message, "'$' must be on a line of its own");
+        let fixed = apply_edits(input, &diag.fixes[0].edits);
+        assert_eq!(fixed, "@part a {\n  $\n  oops\n}\n");
+
+        let parsed = parse_document("@part a {\n  $\n  text\n}\n");
+        assert_eq!(shape(&parsed.document.nodes), "part{${t}}");
+        let diag = &parsed.diagnostics[1];
+        assert_eq!(diag.code, DiagnosticCode::UnterminatedRegion);
+        assert_eq!(diag.span.start.line, 1);
+        assert_eq!((diag.span.start.col8, diag.span.end.col8), (2, 3));
+        assert_eq!(diag.related[0].span.start.line, 3);
+    }
+
+    #[test]
+    fn reports_misplaced_and_duplicate_attributes() {
+        let input = "@node {\n  #id: x\n  text\n  #class: y\n}\n";
+        let parsed = parse_document(input);
+        assert_eq!(parsed.diagnostics.len(), 1);
+        let diag = &parsed.diagnostics[0];
+        assert_eq!(diag.code, DiagnosticCode::MisplacedAttribute);
+        assert_eq!(diag.span.start.line, 3);
+        let fixed = apply_edits(input, &diag.fixes[0].edits);
+        assert_eq!(fixed, "@node {\n  #class: y\n  #id: x\n  text\n}\n");
+        let reparsed = parse_document(&fixed);
+        assert!(reparsed.diagnostics.is_empty());
+        let Node::Block(block) = &reparsed.document.nodes[0] else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.attrs.len(), 2);
+
+        let input = "text\n#title: late";
+        let parsed = parse_document(input);
+        let fixed = apply_edits(input, &parsed.diagnostics[0].fixes[0].edits);
+        assert_eq!(fixed, "#title: late\ntext\n");
+
+        let input = "#title: a\n#title: b\ntext\n";
+        let parsed = parse_document(input);
+        assert_eq!(parsed.diagnostics.len(), 1);
+        let diag = &parsed.diagnostics[0];
+        assert_eq!(diag.code, DiagnosticCode::DuplicateAttribute);
+        assert_eq!(diag.message, "duplicate attribute '#title'");
+        assert_eq!(
+            (diag.span.start.line, diag.related[0].span.start.line),
+            (1, 0)
+        );
+        let fixed = apply_edits(input, &diag.fixes[0].edits);
+        assert_eq!(fixed, "#title: a\ntext\n");
+
+        let parsed = parse_document("@code {\n  # note: kept\n}\n");
+        assert!(parsed.diagnostics.is_empty());
+
+        // Blank space after the `{` still ends the header line.
+        let input = "@node { \t\n  #id: x\n  text\n  #class: y\n}\n";
+        let parsed = parse_document(input);
+        assert_eq!(parsed.diagnostics.len(), 1);
+        let Node::Block(block) = &parsed.document.nodes[0] else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.attrs.len(), 1);
+        let fixed = apply_edits(input, &parsed.diagnostics[0].fixes[0].edits);
+        assert_eq!(fixed, "@node { \t\n  #class: y\n  #id: x\n  text\n}\n");
+    }
 }
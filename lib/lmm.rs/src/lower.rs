@@ -0,0 +1,563 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use smol_str::SmolStr;
+
+use crate::ast::{
+    Attribute, Block, Diagnostic, DiagnosticCode, Document, Edit, Inline, InlineText, Node,
+    Position, Region, Severity, Span, Text, TextLine,
+};
+use crate::inline::{parse_inlines, unescape};
+use crate::messages::message;
+use crate::parser::{ParseOptions, attribute_key};
+use crate::reparse::shift_block;
+use crate::schema::{BUILTIN_BLOCKS, is_raw_block, validate_block, validate_inlines};
+use crate::syntax::{GreenNode, LineIndex, SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+
+/// Parts of a previous lowering that can be carried over unchanged.
+pub(crate) struct Reuse {
+    /// Byte range that was parsed again; diagnostics are only emitted inside it.
+    pub(crate) fresh: Range<usize>,
+    /// Previously lowered top-level blocks keyed by their green node.
+    pub(crate) blocks: HashMap<*const GreenNode, Block>,
+}
+
+impl Reuse {
+    /// Lowers everything from scratch.
+    pub(crate) fn none() -> Self {
+        Self {
+            fresh: 0..usize::MAX,
+            blocks: HashMap::new(),
+        }
+    }
+}
+
+/// Derives the abstract [`Document`] from a lossless syntax tree.
+pub(crate) fn lower_document(
+    root: &SyntaxNode,
+    input: &str,
+    options: ParseOptions,
+    diagnostics: &mut Vec<Diagnostic>,
+    reuse: Reuse,
+) -> Document {
+    let mut lowering = Lowering {
+        index: LineIndex::new(input),
+        options,
+        raw_depth: 0,
+        reuse,
+        diagnostics,
+    };
+    lowering.document(root)
+}
+
+struct Lowering<'a, 'd> {
+    index: LineIndex<'a>,
+    options: ParseOptions,
+    raw_depth: usize,
+    reuse: Reuse,
+    diagnostics: &'d mut Vec<Diagnostic>,
+}
+
+struct LineBuf {
+    offset: usize,
+    indent: usize,
+    value: String,
+    span: Span,
+    line_start: bool,
+    is_comment: bool,
+}
+
+impl Lowering<'_, '_> {
+    fn document(&mut self, root: &SyntaxNode) -> Document {
+        let attrs = self.attributes(root);
+        self.misplaced_attributes(root, Some(0));
+        let nodes = self.nodes(root);
+        Document { attrs, nodes }
+    }
+
+    fn attributes(&mut self, parent: &SyntaxNode) -> Vec<Attribute> {
+        let mut attrs: Vec<Attribute> = Vec::new();
+        for node in parent
+            .child_nodes()
+            .filter(|node| node.kind() == SyntaxKind::AttrLine)
+        {
+            let start = node.text_range().start;
+            let attr = Attribute {
+                key: token_text(&node, SyntaxKind::AttrKey).into(),
+                value: unescape(&token_text(&node, SyntaxKind::AttrValue), false).into(),
+                span: self.index.span(node.text_range()),
+            };
+            if self.is_fresh(start)
+                && let Some(first) = attrs.iter().find(|first| first.key == attr.key)
+            {
+                let locale = self.options.locale;
+                let code = DiagnosticCode::DuplicateAttribute;
+                let args = [("key", attr.key.as_str())];
+                let removal = Edit {
+                    span: self.index.span(self.index.line_range(start)),
+                    new_text: SmolStr::default(),
+                };
+                let diagnostic = Diagnostic::new(
+                    code,
+                    Severity::Warning,
+                    attr.span,
+                    message(locale, code, "", &args),
+                )
+                .with_related(first.span, message(locale, code, "note", &args))
+                .with_fix(message(locale, code, "fix", &args), vec![removal]);
+                self.diagnostics.push(diagnostic);
+            }
+            attrs.push(attr);
+        }
+        attrs
+    }
+
+    /// Warns about attribute lines among the text lines of `parent`, which
+    /// come too late to be attributes. The fix moves them to line `zone`, the
+    /// start of the attribute zone, if known.
+    fn misplaced_attributes(&mut self, parent: &SyntaxNode, zone: Option<usize>) {
+        for node in parent
+            .child_nodes()
+            .filter(|node| node.kind() == SyntaxKind::TextLine)
+        {
+            let start = node.text_range().start;
+            if !self.index.is_line_start(start) || !self.is_fresh(start) {
+                continue;
+            }
+            let line = node.text();
+            let Some(key) = attribute_key(&line) else {
+                continue;
+            };
+            let locale = self.options.locale;
+            let code = DiagnosticCode::MisplacedAttribute;
+            let args = [("key", key)];
+            let span = self.index.span(node.text_range());
+            let mut diagnostic = Diagnostic::new(
+                code,
+                Severity::Warning,
+                span,
+                message(locale, code, "", &args),
+            );
+            if let Some(zone) = zone {
+                let edits = vec![
+                    Edit::insert(Position::new(zone, 0, 0, 0), format!("{line}\n")),
+                    Edit {
+                        span: self.index.span(self.index.line_range(start)),
+                        new_text: SmolStr::default(),
+                    },
+                ];
+                diagnostic = diagnostic.with_fix(message(locale, code, "fix", &args), edits);
+            }
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    fn nodes(&mut self, parent: &SyntaxNode) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut text_buf = Vec::new();
+        // Whether everything since the last newline was whitespace.
+        let mut blank = false;
+
+        for element in parent.children() {
+            let child = match element {
+                SyntaxElement::Node(node) => node,
+                SyntaxElement::Token(token) => {
+                    match token.kind() {
+                        SyntaxKind::Newline => {
+                            // Blank lines end a paragraph, except in code blocks
+                            // where they are part of the content.
+                            if blank && self.raw_depth > 0 {
+                                text_buf.push(self.blank_line(token.text_range().start));
+                            } else if blank {
+                                self.flush_text(&mut nodes, &mut text_buf);
+                            }
+                            blank = true;
+                        }
+                        SyntaxKind::Whitespace => {}
+                        _ => blank = false,
+                    }
+                    continue;
+                }
+            };
+            blank = false;
+            match child.kind() {
+                SyntaxKind::TextLine => text_buf.push(self.text_line(&child)),
+                SyntaxKind::CommentLine => text_buf.push(self.comment_line(&child)),
+                SyntaxKind::DollarRegion => {
+                    self.flush_text(&mut nodes, &mut text_buf);
+                    let region = self.region(&child);
+                    nodes.push(Node::Region(region));
+                }
+                SyntaxKind::Block => {
+                    self.flush_text(&mut nodes, &mut text_buf);
+                    let block = match self.reuse.blocks.remove(&Arc::as_ptr(child.green())) {
+                        Some(old) => self.reused_block(old, &child),
+                        None => self.block(&child),
+                    };
+                    nodes.push(Node::Block(block));
+                }
+                _ => {}
+            }
+        }
+
+        self.flush_text(&mut nodes, &mut text_buf);
+        nodes
+    }
+
+    /// Lowers a `$` region, spanning its opening `$` and, when present, the
+    /// closing one.
+    fn region(&mut self, node: &SyntaxNode) -> Region {
+        let mut dollars = node
+            .child_tokens()
+            .filter(|token| token.kind() == SyntaxKind::Dollar)
+            .map(|token| self.index.span(token.text_range()));
+        let span = dollars
+            .next()
+            .unwrap_or_else(|| self.index.span(node.text_range()));
+        let close_span = dollars.next();
+        Region {
+            nodes: self.nodes(node),
+            span,
+            close_span,
+        }
+    }
+
+    /// Moves a previously lowered block to the line its node now starts on.
+    fn reused_block(&self, mut block: Block, node: &SyntaxNode) -> Block {
+        let line = self.index.position(node.text_range().start).line;
+        let delta = line as isize - block.span.start.line as isize;
+        if delta != 0 {
+            shift_block(&mut block, delta);
+        }
+        block
+    }
+
+    fn block(&mut self, node: &SyntaxNode) -> Block {
+        let header = node
+            .first_child_node(SyntaxKind::Header)
+            .expect("block without header");
+        let tokens: Vec<SyntaxToken> = header.child_tokens().collect();
+        let mut name = SmolStr::default();
+        let mut args = Vec::new();
+        let mut raw_params = Vec::new();
+        let mut plus_count = 0usize;
+        let mut missing_space = false;
+        for (index, token) in tokens.iter().enumerate() {
+            match token.kind() {
+                SyntaxKind::Name => {
+                    name = token.text().into();
+                    missing_space = tokens
+                        .get(index + 1)
+                        .is_some_and(|next| next.kind() == SyntaxKind::LBrace);
+                }
+                SyntaxKind::Arg => args.push(unescape(token.text(), false).into()),
+                SyntaxKind::Params => raw_params.extend(parse_params(token.text())),
+                SyntaxKind::Plus => plus_count = token.text().len(),
+                _ => {}
+            }
+        }
+
+        let start = tokens
+            .iter()
+            .find(|token| token.kind() == SyntaxKind::At)
+            .map_or(header.text_range().start, |token| token.text_range().start);
+        let span = self.index.span(start..header.text_range().end);
+        if missing_space && self.is_fresh(start) {
+            let brace = tokens
+                .iter()
+                .find(|token| token.kind() == SyntaxKind::LBrace)
+                .map_or(span.end, |token| {
+                    self.index.position(token.text_range().start)
+                });
+            let diagnostic = Diagnostic::new(
+                DiagnosticCode::MissingSpace,
+                Severity::Warning,
+                span,
+                message(self.options.locale, DiagnosticCode::MissingSpace, "", &[]),
+            )
+            .with_fix(
+                message(
+                    self.options.locale,
+                    DiagnosticCode::MissingSpace,
+                    "fix",
+                    &[],
+                ),
+                vec![Edit::insert(brace, " ")],
+            );
+            self.diagnostics.push(diagnostic);
+        }
+
+        let attrs = self.attributes(node);
+        let raw = is_raw_block(&name);
+        if !raw {
+            // The attribute zone starts on the line after the header, unless
+            // content follows the `{` on the same line. Blank space after the
+            // `{` is not content.
+            let header_end = node
+                .child_tokens()
+                .find(|token| token.text_range().start == header.text_range().end)
+                .filter(|token| token.kind() == SyntaxKind::Whitespace)
+                .map_or(header.text_range().end, |token| token.text_range().end);
+            let zone = self
+                .index
+                .is_line_start(header_end + 1)
+                .then(|| self.index.position(header_end).line + 1);
+            self.misplaced_attributes(node, zone);
+        }
+        if raw {
+            self.raw_depth += 1;
+        }
+        let mut children = self.nodes(node);
+        if raw {
+            self.raw_depth -= 1;
+            let header_indent = match tokens.first() {
+                Some(token) if token.kind() == SyntaxKind::Whitespace => {
+                    self.indent_width(token.text())
+                }
+                _ => 0,
+            };
+            self.dedent_code(node, header_indent, &mut children);
+        }
+        let close_span = node
+            .first_child_token(SyntaxKind::Close)
+            .map(|token| self.index.span(token.text_range()));
+
+        let block = Block {
+            name,
+            args,
+            params: raw_params
+                .into_iter()
+                .map(|(key, value)| Attribute { key, value, span })
+                .collect(),
+            attrs,
+            nodes: children,
+            plus_count,
+            span,
+            close_span,
+        };
+        if self.options.validate && self.raw_depth == 0 && self.is_fresh(start) {
+            validate_block(
+                &block,
+                BUILTIN_BLOCKS,
+                self.options.locale,
+                self.diagnostics,
+            );
+        }
+        block
+    }
+
+    /// De-indents the lines of a code block relative to its first line,
+    /// keeping their relative indentation and warning about lines that break it.
+    fn dedent_code(&mut self, node: &SyntaxNode, header_indent: usize, children: &mut [Node]) {
+        let lines = children
+            .iter_mut()
+            .filter_map(|child| match child {
+                Node::Text(text) => Some(text.lines.iter_mut()),
+                Node::Block(_) | Node::Region(_) => None,
+            })
+            .flatten()
+            // Blank lines have no syntax node of their own.
+            .filter(|line| !line.value.is_empty());
+        let nodes = node
+            .child_nodes()
+            .filter(|child| child.kind() == SyntaxKind::TextLine);
+        let mut base = None;
+        let mut indent_char = None;
+        for (line, syntax) in lines.zip(nodes) {
+            let start = syntax.text_range().start;
+            if !self.index.is_line_start(start) {
+                continue;
+            }
+            let fresh = self.is_fresh(start);
+            let span = self.index.span(syntax.text_range());
+            let base = match base {
+                Some(base) => base,
+                None => {
+                    if fresh && line.indent <= header_indent {
+                        self.push_diag(
+                            DiagnosticCode::CodeIndentation,
+                            span,
+                            Severity::Warning,
+                            "header",
+                        );
+                    }
+                    *base.insert(line.indent)
+                }
+            };
+            if fresh && line.indent < base {
+                self.push_diag(DiagnosticCode::CodeIndentation, span, Severity::Warning, "");
+            }
+            let token = syntax.first_child_token(SyntaxKind::Whitespace);
+            let whitespace = token.as_ref().map_or("", |token| token.text());
+            let expected = whitespace
+                .chars()
+                .next()
+                .map(|first| *indent_char.get_or_insert(first));
+            if fresh && whitespace.chars().any(|ch| Some(ch) != expected) {
+                self.push_diag(
+                    DiagnosticCode::MixedIndentation,
+                    span,
+                    Severity::Warning,
+                    "",
+                );
+            }
+            line.indent = line.indent.saturating_sub(base);
+        }
+    }
+
+    fn text_line(&self, node: &SyntaxNode) -> LineBuf {
+        let indent = node
+            .first_child_token(SyntaxKind::Whitespace)
+            .map_or(0, |token| self.indent_width(token.text()));
+        let text = node
+            .first_child_token(SyntaxKind::Text)
+            .expect("text line without text");
+        LineBuf {
+            offset: node.text_range().start,
+            indent,
+            value: text.text().to_string(),
+            span: self.index.span(text.text_range()),
+            line_start: self.index.is_line_start(node.text_range().start),
+            is_comment: false,
+        }
+    }
+
+    fn blank_line(&self, offset: usize) -> LineBuf {
+        LineBuf {
+            offset,
+            indent: 0,
+            value: String::new(),
+            span: self.index.span(offset..offset),
+            line_start: true,
+            is_comment: false,
+        }
+    }
+
+    fn comment_line(&self, node: &SyntaxNode) -> LineBuf {
+        let indent = node
+            .first_child_token(SyntaxKind::Whitespace)
+            .map_or(0, |token| self.indent_width(token.text()));
+        let comment = node
+            .first_child_token(SyntaxKind::Comment)
+            .expect("comment line without comment");
+        let rest = &comment.text()[1..];
+        let value = rest.trim_start();
+        let end = comment.text_range().end;
+        LineBuf {
+            offset: node.text_range().start,
+            indent,
+            value: value.to_string(),
+            span: self.index.span(end - value.len()..end),
+            line_start: true,
+            is_comment: true,
+        }
+    }
+
+    fn indent_width(&self, whitespace: &str) -> usize {
+        whitespace
+            .chars()
+            .map(|ch| match ch {
+                '\t' => self.options.tab_width,
+                _ => self.options.space_width,
+            })
+            .sum()
+    }
+
+    fn flush_text(&mut self, nodes: &mut Vec<Node>, text_buf: &mut Vec<LineBuf>) {
+        if text_buf.is_empty() {
+            return;
+        }
+        let mut lines = Vec::with_capacity(text_buf.len());
+        for line in text_buf.drain(..) {
+            lines.push(self.finalize_line(line));
+        }
+        nodes.push(Node::Text(Text { lines }));
+    }
+
+    fn finalize_line(&mut self, line: LineBuf) -> TextLine {
+        if line.is_comment {
+            return TextLine {
+                indent: line.indent,
+                value: unescape(&line.value, false).into(),
+                inlines: Vec::new(),
+                span: line.span,
+                is_comment: true,
+            };
+        }
+        // Raw content is verbatim, escapes included.
+        let value = if self.raw_depth > 0 {
+            line.value.clone()
+        } else {
+            unescape(&line.value, line.line_start)
+        };
+        let mut discarded = Vec::new();
+        let diagnostics = if self.is_fresh(line.offset) {
+            &mut *self.diagnostics
+        } else {
+            &mut discarded
+        };
+        let inlines = if self.raw_depth > 0 {
+            vec![Inline::Text(InlineText {
+                value: value.as_str().into(),
+                span: line.span,
+            })]
+        } else {
+            let locale = self.options.locale;
+            let inlines = parse_inlines(
+                &line.value,
+                line.span.start,
+                line.line_start,
+                locale,
+                diagnostics,
+            );
+            if self.options.validate {
+                validate_inlines(&inlines, BUILTIN_BLOCKS, locale, diagnostics);
+            }
+            inlines
+        };
+        TextLine {
+            indent: line.indent,
+            value: value.into(),
+            inlines,
+            span: line.span,
+            is_comment: false,
+        }
+    }
+
+    fn is_fresh(&self, offset: usize) -> bool {
+        self.reuse.fresh.contains(&offset)
+    }
+
+    /// Pushes the message of `code` selected by `key`.
+    fn push_diag(&mut self, code: DiagnosticCode, span: Span, severity: Severity, key: &str) {
+        let message = message(self.options.locale, code, key, &[]);
+        self.diagnostics
+            .push(Diagnostic::new(code, severity, span, message));
+    }
+}
+
+fn token_text(node: &SyntaxNode, kind: SyntaxKind) -> String {
+    node.first_child_token(kind)
+        .map(|token| token.text().to_string())
+        .unwrap_or_default()
+}
+
+/// Parses a `[key=value, flag]` parameter list.
+fn parse_params(text: &str) -> Vec<(SmolStr, SmolStr)> {
+    let inner = text.strip_prefix('[').unwrap_or(text);
+    let inner = inner.strip_suffix(']').unwrap_or(inner);
+    inner
+        .split(',')
+        .filter(|token| !token.trim().is_empty())
+        .map(parse_param_token)
+        .collect()
+}
+
+fn parse_param_token(token: &str) -> (SmolStr, SmolStr) {
+    let token = token.trim();
+    let Some((key, value)) = token.split_once('=') else {
+        return (token.into(), "".into());
+    };
+    (key.trim().into(), unescape(value.trim(), false).into())
+}
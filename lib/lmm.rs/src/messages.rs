@@ -0,0 +1,324 @@
+use data_classes::derive::*;
+
+use crate::ast::DiagnosticCode;
+
+/// Language of diagnostic messages.
+#[data(copy)]
+pub enum Locale {
+    /// English.
+    En,
+    /// Simplified Chinese.
+    ZhCn,
+}
+
+impl Locale {
+    /// Picks the locale for a BCP 47 tag such as `en-US` or `zh-CN`, if supported.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag.split(['-', '_']).next().unwrap_or(tag);
+        match language.to_ascii_lowercase().as_str() {
+            "en" => Some(Locale::En),
+            "zh" => Some(Locale::ZhCn),
+            _ => None,
+        }
+    }
+
+    /// The BCP 47 tag of the locale.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::ZhCn => "zh-CN",
+        }
+    }
+}
+
+/// A message template in every supported locale.
+struct Entry {
+    code: DiagnosticCode,
+    /// Tells apart several messages of one code; empty for the main message.
+    key: &'static str,
+    en: &'static str,
+    zh_cn: &'static str,
+}
+
+/// Message templates keyed by diagnostic code. `{name}` placeholders are
+/// filled in by [`message`].
+const CATALOG: &[Entry] = &[
+    Entry {
+        code: DiagnosticCode::MissingSpace,
+        key: "",
+        en: "missing space between block name and '{'",
+        zh_cn: "标记名称与左大括号之间缺少空格",
+    },
+    Entry {
+        code: DiagnosticCode::MissingSpace,
+        key: "fix",
+        en: "Insert space",
+        zh_cn: "插入空格",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedBlock,
+        key: "",
+        en: "unclosed block, expected '{closer}'",
+        zh_cn: "块未闭合，缺少 '{closer}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedBlock,
+        key: "near-miss",
+        en: "'{found}' does not close this block, which needs '{closer}'",
+        zh_cn: "'{found}' 无法闭合此块，需要 '{closer}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedBlock,
+        key: "fix",
+        en: "Insert '{closer}'",
+        zh_cn: "插入 '{closer}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedBlock,
+        key: "complete",
+        en: "Close '@{name}'",
+        zh_cn: "结束 '@{name}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedInline,
+        key: "",
+        en: "unclosed inline group",
+        zh_cn: "行内标记未闭合",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedInline,
+        key: "fix",
+        en: "Insert '}'",
+        zh_cn: "插入 '}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedInlineCode,
+        key: "",
+        en: "unclosed inline code",
+        zh_cn: "行内代码未闭合",
+    },
+    Entry {
+        code: DiagnosticCode::UnclosedInlineCode,
+        key: "fix",
+        en: "Insert '``'",
+        zh_cn: "插入 '``'",
+    },
+    Entry {
+        code: DiagnosticCode::MissingOpenBrace,
+        key: "",
+        en: "block header missing opening delimiter",
+        zh_cn: "块标记缺少左大括号",
+    },
+    Entry {
+        code: DiagnosticCode::MissingOpenBrace,
+        key: "fix",
+        en: "Insert '{'",
+        zh_cn: "插入 '{'",
+    },
+    Entry {
+        code: DiagnosticCode::MissingBlockName,
+        key: "",
+        en: "missing block name",
+        zh_cn: "缺少标记名称",
+    },
+    Entry {
+        code: DiagnosticCode::UnterminatedRegion,
+        key: "",
+        en: "unterminated '$' region, end it with a line holding only '$'",
+        zh_cn: "'$' 区域未结束，需要以单独一行的 '$' 结束",
+    },
+    Entry {
+        code: DiagnosticCode::UnterminatedRegion,
+        key: "note",
+        en: "the region runs to the end of the document",
+        zh_cn: "该区域一直延续到文档末尾",
+    },
+    Entry {
+        code: DiagnosticCode::UnterminatedRegion,
+        key: "fix",
+        en: "Insert '$'",
+        zh_cn: "插入 '$'",
+    },
+    Entry {
+        code: DiagnosticCode::MalformedAttribute,
+        key: "",
+        en: "attribute missing ':'",
+        zh_cn: "属性缺少 ':'",
+    },
+    Entry {
+        code: DiagnosticCode::MalformedAttribute,
+        key: "empty-key",
+        en: "attribute key is empty",
+        zh_cn: "属性名为空",
+    },
+    Entry {
+        code: DiagnosticCode::UnknownBlock,
+        key: "",
+        en: "unknown block '@{name}'",
+        zh_cn: "未知的块 '@{name}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnknownParam,
+        key: "",
+        en: "unknown param '{param}' for '@{name}'",
+        zh_cn: "'@{name}' 不支持参数 '{param}'",
+    },
+    Entry {
+        code: DiagnosticCode::InvalidParamValue,
+        key: "",
+        en: "invalid value '{value}' for param '{param}', expected one of: {expected}",
+        zh_cn: "参数 '{param}' 的值 '{value}' 无效，应为以下之一：{expected}",
+    },
+    Entry {
+        code: DiagnosticCode::InvalidParamValue,
+        key: "flag",
+        en: "param '{param}' does not take a value",
+        zh_cn: "参数 '{param}' 不接受值",
+    },
+    Entry {
+        code: DiagnosticCode::InvalidParamValue,
+        key: "empty",
+        en: "param '{param}' needs a value",
+        zh_cn: "参数 '{param}' 需要一个值",
+    },
+    Entry {
+        code: DiagnosticCode::ConflictingParams,
+        key: "",
+        en: "params '{first}' and '{second}' of '@{name}' cannot be used together",
+        zh_cn: "'@{name}' 的参数 '{first}' 和 '{second}' 不能同时使用",
+    },
+    Entry {
+        code: DiagnosticCode::MissingAttribute,
+        key: "",
+        en: "'@{name}' requires attribute '#{attr}'",
+        zh_cn: "'@{name}' 需要属性 '#{attr}'",
+    },
+    Entry {
+        code: DiagnosticCode::DisallowedChild,
+        key: "",
+        en: "'@{child}' is not allowed inside '@{name}'",
+        zh_cn: "'@{name}' 中不允许出现 '@{child}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnknownInline,
+        key: "",
+        en: "unknown inline marker '@{name}'",
+        zh_cn: "未知的行内标记 '@{name}'",
+    },
+    Entry {
+        code: DiagnosticCode::UnexpectedArgument,
+        key: "",
+        en: "'@{name}' does not take arguments",
+        zh_cn: "'@{name}' 不接受参数",
+    },
+    Entry {
+        code: DiagnosticCode::MissingArgument,
+        key: "",
+        en: "'@{name}' needs an argument, as in '@{name}(...)'",
+        zh_cn: "'@{name}' 需要一个参数，例如 '@{name}(...)'",
+    },
+    Entry {
+        code: DiagnosticCode::MissingArgument,
+        key: "block",
+        en: "'@{name}' needs an argument, as in '@{name} ... {'",
+        zh_cn: "'@{name}' 需要一个参数，例如 '@{name} ... {'",
+    },
+    Entry {
+        code: DiagnosticCode::CodeIndentation,
+        key: "",
+        en: "code line is indented less than the first line of its block",
+        zh_cn: "代码行的缩进少于代码块的第一行",
+    },
+    Entry {
+        code: DiagnosticCode::CodeIndentation,
+        key: "header",
+        en: "code block content should be indented past its header",
+        zh_cn: "代码块的内容应比块标记缩进更多",
+    },
+    Entry {
+        code: DiagnosticCode::MixedIndentation,
+        key: "",
+        en: "code line mixes tabs and spaces in its indentation",
+        zh_cn: "代码行的缩进混用了制表符和空格",
+    },
+    Entry {
+        code: DiagnosticCode::DollarContent,
+        key: "",
+        en: "'$' must be on a line of its own",
+        zh_cn: "'$' 必须独占一行",
+    },
+    Entry {
+        code: DiagnosticCode::DollarContent,
+        key: "fix",
+        en: "Move content to the next line",
+        zh_cn: "将内容移到下一行",
+    },
+    Entry {
+        code: DiagnosticCode::MisplacedAttribute,
+        key: "",
+        en: "attribute '#{key}' comes after content and is read as text; attributes must come first",
+        zh_cn: "属性 '#{key}' 出现在内容之后，会被当作文本；属性必须写在最前面",
+    },
+    Entry {
+        code: DiagnosticCode::MisplacedAttribute,
+        key: "fix",
+        en: "Move attribute up",
+        zh_cn: "将属性移到前面",
+    },
+    Entry {
+        code: DiagnosticCode::DuplicateAttribute,
+        key: "",
+        en: "duplicate attribute '#{key}'",
+        zh_cn: "重复的属性 '#{key}'",
+    },
+    Entry {
+        code: DiagnosticCode::DuplicateAttribute,
+        key: "note",
+        en: "first given here",
+        zh_cn: "首次出现在这里",
+    },
+    Entry {
+        code: DiagnosticCode::DuplicateAttribute,
+        key: "fix",
+        en: "Remove duplicate attribute",
+        zh_cn: "删除重复的属性",
+    },
+    Entry {
+        code: DiagnosticCode::DanglingReference,
+        key: "",
+        en: "no block has id '{id}'",
+        zh_cn: "没有 id 为 '{id}' 的块",
+    },
+    Entry {
+        code: DiagnosticCode::DuplicateId,
+        key: "",
+        en: "id '{id}' is already used by another block",
+        zh_cn: "id '{id}' 已被其他块使用",
+    },
+    Entry {
+        code: DiagnosticCode::DuplicateId,
+        key: "note",
+        en: "first used here",
+        zh_cn: "首次使用在这里",
+    },
+];
+
+/// Formats the message of `code` selected by `key` (empty for the main
+/// message) in `locale`, filling `{name}` placeholders from `args`.
+pub fn message(locale: Locale, code: DiagnosticCode, key: &str, args: &[(&str, &str)]) -> String {
+    let Some(entry) = CATALOG
+        .iter()
+        .find(|entry| entry.code == code && entry.key == key)
+    else {
+        return code.as_str().to_string();
+    };
+    let template = match locale {
+        Locale::En => entry.en,
+        Locale::ZhCn => entry.zh_cn,
+    };
+    let mut out = template.to_string();
+    for (name, value) in args {
+        out = out.replace(&format!("{{{name}}}"), value);
+    }
+    out
+}
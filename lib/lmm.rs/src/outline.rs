@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use data_classes::derive::*;
+use smol_str::SmolStr;
+
+use crate::anchor::{block_anchors, block_key, is_section, section_title};
+use crate::ast::{Block, Document, Node, Span};
+use crate::backend::section_depth;
+
+/// A section of the document outline.
+#[data]
+pub struct OutlineEntry {
+    /// Heading text of the section.
+    pub title: SmolStr,
+    /// Anchor id of the section, as rendered on its heading.
+    pub id: SmolStr,
+    /// Depth of the section, `1` for a section outside any other or
+    /// directly inside a list, as counted by [`section_depth`].
+    pub level: usize,
+    /// Span of the whole section block, as given by
+    /// [`Block::full_span`](crate::Block::full_span).
+    pub span: Span,
+    /// Span of the section header, from `@` through the opening `{`.
+    pub header_span: Span,
+    /// Sections directly inside this one.
+    pub children: Vec<OutlineEntry>,
+}
+
+/// Builds the tree of sections of `document`.
+///
+/// Sections are nested by the level of the heading they render as. Since
+/// sections inside a list start over at the top level, they follow the
+/// section enclosing the list instead of nesting under it.
+pub fn outline(document: &Document) -> Vec<OutlineEntry> {
+    fn visit<'d>(
+        nodes: &'d [Node],
+        ids: &HashMap<(usize, usize), SmolStr>,
+        ancestors: &mut Vec<&'d Block>,
+        entries: &mut Vec<OutlineEntry>,
+    ) {
+        for node in nodes {
+            match node {
+                Node::Block(block) => {
+                    if is_section(block) {
+                        let id = ids.get(&block_key(block)).cloned().unwrap_or_default();
+                        entries.push(OutlineEntry {
+                            title: section_title(block).into(),
+                            id,
+                            level: section_depth(ancestors) + 1,
+                            span: block.full_span(),
+                            header_span: block.span,
+                            children: Vec::new(),
+                        });
+                    }
+                    ancestors.push(block);
+                    visit(&block.nodes, ids, ancestors, entries);
+                    ancestors.pop();
+                }
+                Node::Region(region) => visit(&region.nodes, ids, ancestors, entries),
+                Node::Text(_) => {}
+            }
+        }
+    }
+
+    let ids = block_anchors(document)
+        .into_iter()
+        .map(|(block, anchor)| (block_key(block), anchor.id))
+        .collect();
+    let mut entries = Vec::new();
+    visit(&document.nodes, &ids, &mut Vec::new(), &mut entries);
+    nest(entries)
+}
+
+/// Turns entries in document order into a tree, placing each one under the
+/// closest earlier entry of a lower level.
+fn nest(entries: Vec<OutlineEntry>) -> Vec<OutlineEntry> {
+    fn attach(open: &mut [OutlineEntry], roots: &mut Vec<OutlineEntry>, entry: OutlineEntry) {
+        match open.last_mut() {
+            Some(parent) => parent.children.push(entry),
+            None => roots.push(entry),
+        }
+    }
+
+    let mut roots = Vec::new();
+    let mut open: Vec<OutlineEntry> = Vec::new();
+    for entry in entries {
+        while let Some(last) = open.pop_if(|last| last.level >= entry.level) {
+            attach(&mut open, &mut roots, last);
+        }
+        open.push(entry);
+    }
+    while let Some(last) = open.pop() {
+        attach(&mut open, &mut roots, last);
+    }
+    roots
+}
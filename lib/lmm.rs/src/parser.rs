@@ -1,9 +1,12 @@
 use data_classes::derive::*;
-use smol_str::SmolStr;
 
-use crate::ast::{
-    Attribute, Block, Diagnostic, Document, Node, Position, Severity, Span, Text, TextLine,
-};
+use crate::anchor::check_references;
+use crate::ast::{Diagnostic, DiagnosticCode, Document, Edit, Position, Severity, Span};
+use crate::inline::{find_inline_close, is_inline_marker, marker_name};
+use crate::lower::{Reuse, lower_document};
+use crate::messages::{Locale, message};
+use crate::schema::is_raw_block;
+use crate::syntax::{SyntaxKind, SyntaxNode, TreeBuilder};
 
 #[data(default, copy)]
 pub struct ParseOptions {
@@ -11,10 +14,19 @@ pub struct ParseOptions {
     pub space_width: usize,
     #[default = 2]
     pub tab_width: usize,
+    /// Check blocks and inline markers against the built-in schemas.
+    #[default = true]
+    pub validate: bool,
+    /// Language of diagnostic messages.
+    #[default = Locale::En]
+    pub locale: Locale,
 }
 
 #[data]
 pub struct ParseResult {
+    /// Lossless syntax tree; `syntax.text()` reproduces the input exactly.
+    pub syntax: SyntaxNode,
+    /// Abstract document lowered from `syntax`.
     pub document: Document,
     pub diagnostics: Vec<Diagnostic>,
 }
@@ -24,175 +36,303 @@ pub fn parse_document(input: &str) -> ParseResult {
 }
 
 pub fn parse_document_with_options(input: &str, options: ParseOptions) -> ParseResult {
-    let mut parser = Parser::new(input, options);
-    let document = parser.parse_document();
+    let (syntax, mut diagnostics) = parse_syntax_in(input, options.locale);
+    let document = lower_document(&syntax, input, options, &mut diagnostics, Reuse::none());
+    if options.validate {
+        diagnostics.extend(check_references(&document, options.locale));
+    }
+    diagnostics.sort_by_key(|diag| (diag.span.start.line, diag.span.start.col8));
     ParseResult {
+        syntax,
         document,
+        diagnostics,
+    }
+}
+
+/// Parses `input` into a lossless syntax tree plus structural diagnostics.
+pub fn parse_syntax(input: &str) -> (SyntaxNode, Vec<Diagnostic>) {
+    parse_syntax_in(input, Locale::En)
+}
+
+fn parse_syntax_in(input: &str, locale: Locale) -> (SyntaxNode, Vec<Diagnostic>) {
+    let mut builder = TreeBuilder::new();
+    builder.start_node(SyntaxKind::Document);
+    let mut partial = parse_partial(input, builder, 0, 0, &[], locale);
+    partial.builder.finish_node();
+    let root = partial.builder.finish();
+    (SyntaxNode::new_root(root), partial.diagnostics)
+}
+
+/// Top-level items parsed by [`parse_partial`].
+pub(crate) struct PartialParse {
+    pub(crate) builder: TreeBuilder,
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// Index into the sync points at which parsing stopped, if any.
+    pub(crate) synced_at: Option<usize>,
+    /// Byte offset at which parsing stopped.
+    pub(crate) end: usize,
+    /// Line at which parsing stopped.
+    pub(crate) end_line: usize,
+}
+
+/// Parses top-level items of `input` into `builder`, starting at the line
+/// beginning at byte `start` (line number `line`).
+///
+/// Parsing stops early when the top level reaches a line start listed in the
+/// sorted `sync_points`. Attributes are only recognized when `start` is zero.
+/// Diagnostic messages are written in `locale`.
+pub(crate) fn parse_partial(
+    input: &str,
+    builder: TreeBuilder,
+    start: usize,
+    line: usize,
+    sync_points: &[usize],
+    locale: Locale,
+) -> PartialParse {
+    let mut parser = Parser {
+        input,
+        idx: start,
+        line_start_idx: start,
+        pos: Position::new(line, 0, 0, 0),
+        raw_depth: 0,
+        sync_points,
+        synced_at: None,
+        builder,
+        diagnostics: Vec::new(),
+        locale,
+        depth: 0,
+        recovering: false,
+    };
+    if start == 0 {
+        parser.parse_attributes_at_start();
+    }
+    parser.parse_nodes_until(None);
+    PartialParse {
+        builder: parser.builder,
         diagnostics: parser.diagnostics,
+        synced_at: parser.synced_at,
+        end: parser.idx,
+        end_line: parser.pos.line,
     }
 }
 
+/// The end of the block being parsed by [`Parser::parse_nodes_until`].
+struct Closer<'c> {
+    /// Closing delimiter, such as `}++`.
+    delim: &'c str,
+    /// Indentation of the block's header in bytes.
+    indent: usize,
+}
+
+/// How [`Parser::parse_nodes_until`] stopped.
+enum Until {
+    /// The closer was found.
+    Closed,
+    /// The input ended, or a block header not indented past the open block
+    /// showed that its closer is missing.
+    Open {
+        /// The last closer seen with the wrong number of `+` signs.
+        near_miss: Option<NearMiss>,
+    },
+}
+
+struct NearMiss {
+    span: Span,
+    closer: String,
+}
+
 struct Parser<'a> {
     input: &'a str,
     idx: usize,
     line_start_idx: usize,
     pos: Position,
-    options: ParseOptions,
+    raw_depth: usize,
+    sync_points: &'a [usize],
+    synced_at: Option<usize>,
+    builder: TreeBuilder,
     diagnostics: Vec<Diagnostic>,
+    locale: Locale,
+    /// Number of blocks currently open.
+    depth: usize,
+    /// Whether an unclosed block is being parsed again with the
+    /// indentation-based recovery of [`Parser::parse_nodes_until`].
+    recovering: bool,
 }
 
 impl<'a> Parser<'a> {
-    fn new(input: &'a str, options: ParseOptions) -> Self {
-        Self {
-            input,
-            idx: 0,
-            line_start_idx: 0,
-            pos: Position::new(0, 0, 0, 0),
-            options,
-            diagnostics: Vec::new(),
-        }
-    }
-
-    fn parse_document(&mut self) -> Document {
-        let attrs = self.parse_attributes_at_start();
-        let nodes = self.parse_nodes_until(None);
-        self.consume_trailing_comments();
-        if !self.at_end() {
-            let span = span_at_line_start(self.pos.line);
-            self.push_diag(span, Severity::Error, "unexpected trailing content");
-        }
-        Document { attrs, nodes }
-    }
-
-    fn parse_nodes_until(&mut self, closing: Option<&str>) -> Vec<Node> {
-        let mut nodes = Vec::new();
-        let mut text_buf: Vec<LineBuf> = Vec::new();
-        let mut closed = closing.is_none();
-
+    /// Parses nodes up to `closing`, reporting whether the closer was found.
+    ///
+    /// While recovering, a block header indented no deeper than the open
+    /// block's own header is taken to follow it, so parsing stops there and the
+    /// block is left unclosed instead of swallowing the rest of the document.
+    fn parse_nodes_until(&mut self, closing: Option<Closer>) -> Until {
+        let mut near_miss = None;
         while !self.at_end() {
-            if let Some(close) = closing {
-                if let Some(close_idx) = self.find_close_in_line(close) {
-                    if close_idx == self.idx {
-                        self.flush_text(&mut nodes, &mut text_buf);
-                        self.advance_to_idx(close_idx + close.len());
-                        closed = true;
-                        break;
-                    }
-                    let line = self.current_line_slice().unwrap_or("");
-                    let start_offset = self.current_line_offset();
-                    let end_offset = close_idx - self.line_start_idx;
-                    if let Some(line_buf) =
-                        self.parse_text_segment(line, self.pos.line, start_offset, end_offset)
-                    {
-                        text_buf.push(line_buf);
-                    }
-                    self.flush_text(&mut nodes, &mut text_buf);
-                    self.advance_to_idx(close_idx + close.len());
-                    closed = true;
-                    break;
-                }
+            if closing.is_none() && self.reached_sync_point() {
+                break;
+            }
+            if let Some(closer) = &closing
+                && let Some(close_idx) = self.find_close_in_line(closer.delim)
+            {
+                self.text_segment(close_idx);
+                self.bump(SyntaxKind::Close, close_idx + closer.delim.len());
+                return Until::Closed;
             }
 
-            if self.is_line_start() {
-                if let Some(line) = self.current_line_slice() {
+            if self.is_line_start()
+                && let Some(line) = self.current_line_slice()
+            {
+                if line.trim().is_empty() {
+                    self.blank_line();
+                    continue;
+                }
+                if self.recovering
+                    && let Some(closer) = &closing
+                    && is_outer_header(line, closer.indent)
+                {
+                    return Until::Open { near_miss };
+                }
+                // Raw content is verbatim: apart from the closer, every line is text.
+                if self.raw_depth == 0 {
                     if is_comment_line(line) {
-                        if let Some(line_buf) =
-                            parse_comment_line(line, self.pos.line, self.options)
-                        {
-                            text_buf.push(line_buf);
-                        }
-                        self.advance_line();
-                        continue;
-                    }
-                    if line.trim().is_empty() {
-                        self.advance_line();
+                        self.comment_line();
                         continue;
                     }
                     if is_dollar_line(line) {
-                        self.flush_text(&mut nodes, &mut text_buf);
-                        self.advance_line();
-                        let raw_lines = self.collect_until_dollar();
-                        let mut lines = Vec::new();
-                        for (line_index, raw) in raw_lines {
-                            if let Some(line_buf) = parse_text_line(raw, line_index, self.options) {
-                                lines.push(line_buf);
-                            }
-                        }
-                        if !lines.is_empty() {
-                            let text = finalize_text(lines);
-                            nodes.push(Node::Text(text));
-                        }
+                        self.dollar_region();
                         continue;
                     }
-                    if let Some(block) = self.try_parse_block_header() {
-                        self.flush_text(&mut nodes, &mut text_buf);
-                        let attrs = self.parse_attributes_at_start();
-                        let close_delim = block_close_delim(block.plus_count);
-                        let children = self.parse_nodes_until(Some(&close_delim));
-                        nodes.push(Node::Block(Block {
-                            name: block.name,
-                            args: block.args,
-                            params: block.params,
-                            attrs,
-                            nodes: children,
-                            span: block.span,
-                        }));
+                    self.check_dollar_content(line);
+                    if self.try_parse_block() {
                         continue;
                     }
                 }
             }
 
-            if let Some(line) = self.current_line_slice() {
-                let line_end = self.line_end_idx();
-                let start_offset = self.current_line_offset();
-                let end_offset = line_end - self.line_start_idx;
-                if let Some(line_buf) =
-                    self.parse_text_segment(line, self.pos.line, start_offset, end_offset)
-                {
-                    text_buf.push(line_buf);
-                }
-                self.advance_line();
+            if let Some(closer) = &closing {
+                near_miss = self.find_near_miss(closer.delim).or(near_miss);
             }
+            self.text_segment(self.line_end_idx());
+            self.bump_newline();
         }
 
-        self.flush_text(&mut nodes, &mut text_buf);
-        if !closed {
-            let line_index = self.pos.line.saturating_sub(1);
-            let span = span_at_line_start(line_index);
-            self.push_diag(span, Severity::Error, "missing closing delimiter");
+        Until::Open { near_miss }
+    }
+
+    /// Emits the text between the cursor and `end` as a `TextLine`.
+    fn text_segment(&mut self, end: usize) {
+        let segment = &self.input[self.idx..end];
+        let indent = leading_ws_len(segment);
+        if indent == segment.len() {
+            self.bump(SyntaxKind::Whitespace, end);
+            return;
+        }
+        self.builder.start_node(SyntaxKind::TextLine);
+        if self.is_line_start() {
+            self.bump(SyntaxKind::Whitespace, self.idx + indent);
         }
-        nodes
+        self.bump(SyntaxKind::Text, end);
+        self.builder.finish_node();
     }
 
-    fn parse_text_segment(
-        &self,
-        line: &str,
-        line_index: usize,
-        start: usize,
-        end: usize,
-    ) -> Option<LineBuf> {
-        parse_text_segment(line, line_index, start, end, self.options)
+    fn comment_line(&mut self) {
+        let line_end = self.line_end_idx();
+        let indent = leading_ws_len(&self.input[self.idx..line_end]);
+        self.builder.start_node(SyntaxKind::CommentLine);
+        self.bump(SyntaxKind::Whitespace, self.idx + indent);
+        self.bump(SyntaxKind::Comment, line_end);
+        self.builder.finish_node();
+        self.bump_newline();
     }
 
-    fn flush_text(&mut self, nodes: &mut Vec<Node>, text_buf: &mut Vec<LineBuf>) {
-        if text_buf.is_empty() {
-            return;
+    fn blank_line(&mut self) {
+        self.bump(SyntaxKind::Whitespace, self.line_end_idx());
+        self.bump_newline();
+    }
+
+    fn dollar_region(&mut self) {
+        let line = self.current_line_slice().unwrap_or("");
+        let indent = &line[..leading_ws_len(line)];
+        let opener = span_for_line_offsets(self.pos.line, line, indent.len(), indent.len() + 1);
+        self.builder.start_node(SyntaxKind::DollarRegion);
+        self.dollar_line();
+        while !self.at_end() {
+            let line = self.current_line_slice().unwrap_or("");
+            if is_dollar_line(line) {
+                self.dollar_line();
+                self.builder.finish_node();
+                return;
+            }
+            if is_comment_line(line) {
+                self.comment_line();
+            } else if line.trim().is_empty() {
+                self.blank_line();
+            } else {
+                self.check_dollar_content(line);
+                self.text_segment(self.line_end_idx());
+                self.bump_newline();
+            }
         }
-        let mut lines = Vec::with_capacity(text_buf.len());
-        for line in text_buf.drain(..) {
-            let value = unescape_text(&line.value);
-            lines.push(TextLine {
-                indent: line.indent,
-                value: value.into(),
-                span: line.span,
-                is_comment: line.is_comment,
-            });
+        let end = span_at_line_start(self.last_line());
+        let diagnostic = Diagnostic::new(
+            DiagnosticCode::UnterminatedRegion,
+            Severity::Error,
+            opener,
+            self.message(DiagnosticCode::UnterminatedRegion, "", &[]),
+        )
+        .with_related(
+            end,
+            self.message(DiagnosticCode::UnterminatedRegion, "note", &[]),
+        )
+        .with_fix(
+            self.message(DiagnosticCode::UnterminatedRegion, "fix", &[]),
+            vec![self.insert_line_at_end(&format!("{indent}$"))],
+        );
+        self.diagnostics.push(diagnostic);
+        self.builder.finish_node();
+    }
+
+    /// Reports a line such as `$ text`, which reads like a `$` line but has
+    /// content after the `$`. The line itself stays text.
+    fn check_dollar_content(&mut self, line: &str) {
+        let indent = leading_ws_len(line);
+        let Some(rest) = line[indent..].strip_prefix('$') else {
+            return;
+        };
+        let content = rest.trim_start_matches([' ', '\t']);
+        if content.len() == rest.len() || content.trim().is_empty() {
+            return;
         }
-        nodes.push(Node::Text(Text { lines }));
+        let span = line_span_from_line(self.pos.line, line);
+        let gap =
+            span_for_line_offsets(self.pos.line, line, indent + 1, line.len() - content.len());
+        let diagnostic = Diagnostic::new(
+            DiagnosticCode::DollarContent,
+            Severity::Error,
+            span,
+            self.message(DiagnosticCode::DollarContent, "", &[]),
+        )
+        .with_fix(
+            self.message(DiagnosticCode::DollarContent, "fix", &[]),
+            vec![Edit {
+                span: gap,
+                new_text: format!("\n{}", &line[..indent]).into(),
+            }],
+        );
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn dollar_line(&mut self) {
+        let line_end = self.line_end_idx();
+        let line = &self.input[self.idx..line_end];
+        let dollar = self.idx + (line.len() - line.trim_start().len());
+        self.bump(SyntaxKind::Whitespace, dollar);
+        self.bump(SyntaxKind::Dollar, dollar + 1);
+        self.bump(SyntaxKind::Whitespace, line_end);
+        self.bump_newline();
     }
 
-    fn parse_attributes_at_start(&mut self) -> Vec<Attribute> {
-        let mut attrs = Vec::new();
+    fn parse_attributes_at_start(&mut self) {
         loop {
             if !self.is_line_start() {
                 break;
@@ -200,165 +340,262 @@ impl<'a> Parser<'a> {
             let Some(line) = self.current_line_slice() else {
                 break;
             };
-            if is_comment_line(line) {
+            if is_comment_line(line) || line.trim().is_empty() {
                 break;
             }
-            if line.trim().is_empty() {
+            if !self.check_attribute_line(line) {
                 break;
             }
-            if let Some(attr) = self.parse_attribute_line(line) {
-                attrs.push(attr);
-                self.advance_line();
-                continue;
-            }
-            break;
+            self.attribute_line(line);
         }
-        attrs
     }
 
-    fn parse_attribute_line(&mut self, line: &str) -> Option<Attribute> {
+    /// Returns whether `line` is a well-formed attribute line.
+    fn check_attribute_line(&mut self, line: &str) -> bool {
         let trimmed = line.trim_start();
         if !trimmed.starts_with('#') || trimmed.starts_with("##") {
-            return None;
+            return false;
         }
-        let without_hash = &trimmed[1..];
-        let Some((key, value)) = without_hash.split_once(':') else {
+        let Some((key, _)) = trimmed[1..].split_once(':') else {
             let span = line_span_from_line(self.pos.line, line);
-            self.push_diag(span, Severity::Error, "attribute missing ':'");
-            return None;
+            self.push_diag(
+                DiagnosticCode::MalformedAttribute,
+                span,
+                Severity::Error,
+                "",
+            );
+            return false;
         };
-        let key = key.trim();
-        if key.is_empty() {
+        if key.trim().is_empty() {
             let span = line_span_from_line(self.pos.line, line);
-            self.push_diag(span, Severity::Error, "attribute key is empty");
-            return None;
+            self.push_diag(
+                DiagnosticCode::MalformedAttribute,
+                span,
+                Severity::Error,
+                "empty-key",
+            );
+            return false;
         }
-        let span = line_span_from_line(self.pos.line, line);
-        Some(Attribute {
-            key: key.into(),
-            value: value.trim().into(),
-            span,
-        })
+        true
+    }
+
+    fn attribute_line(&mut self, line: &str) {
+        let start = self.idx;
+        let hash = line.len() - line.trim_start().len();
+        let colon = hash + line[hash..].find(':').unwrap_or(line.len() - hash);
+        self.builder.start_node(SyntaxKind::AttrLine);
+        self.bump(SyntaxKind::Whitespace, start + hash);
+        self.bump(SyntaxKind::Hash, start + hash + 1);
+        self.bump_trimmed(SyntaxKind::AttrKey, start + colon);
+        self.bump(SyntaxKind::Colon, start + colon + 1);
+        self.bump_trimmed(SyntaxKind::AttrValue, start + line.len());
+        self.builder.finish_node();
+        self.bump_newline();
     }
 
-    fn try_parse_block_header(&mut self) -> Option<BlockHeader> {
-        let line = self.current_line_slice()?;
-        let (at_col, header_start) = find_block_header_start(line)?;
-        let start_pos = position_for_line_offset(self.pos.line, line, at_col);
-        let start_idx = self.line_start_idx + header_start;
-        let Some((header_raw, header_span, end_idx)) = self.scan_header(start_idx, start_pos)
+    fn try_parse_block(&mut self) -> bool {
+        let Some(line) = self.current_line_slice() else {
+            return false;
+        };
+        let Some(at_col) = find_block_header_start(line) else {
+            return false;
+        };
+        if marker_name(&line[at_col..]).is_some_and(is_inline_marker) {
+            return false;
+        }
+        let start_idx = self.line_start_idx + at_col;
+        let Some(brace_idx) =
+            find_header_brace(&self.input[start_idx..]).map(|idx| start_idx + idx)
         else {
             let span = line_span_from_line(self.pos.line, line);
-            self.push_diag(
-                span,
+            let diagnostic = Diagnostic::new(
+                DiagnosticCode::MissingOpenBrace,
                 Severity::Error,
-                "block header missing opening delimiter",
+                span,
+                self.message(DiagnosticCode::MissingOpenBrace, "", &[]),
+            )
+            .with_fix(
+                self.message(DiagnosticCode::MissingOpenBrace, "fix", &[]),
+                vec![Edit::insert(span.end, " {")],
             );
-            self.advance_line();
-            return None;
+            self.diagnostics.push(diagnostic);
+            self.builder.start_node(SyntaxKind::Error);
+            self.bump(SyntaxKind::Whitespace, start_idx);
+            self.bump(SyntaxKind::Text, self.line_end_idx());
+            self.builder.finish_node();
+            self.bump_newline();
+            return true;
         };
 
-        self.advance_to_idx(end_idx);
-        self.advance_line_if_eol();
-
-        let (name, args, params, plus_count, missing_space) = match parse_header_parts(&header_raw)
-        {
-            Some(value) => value,
-            None => {
-                self.push_diag(header_span, Severity::Error, "missing block name");
-                return None;
+        let header_raw = &self.input[start_idx..brace_idx];
+        let Some(tokens) = tokenize_header(header_raw) else {
+            let start = self.position_at(start_idx);
+            let end = self.position_at(brace_idx + 1);
+            self.push_diag(
+                DiagnosticCode::MissingBlockName,
+                Span::new(start, end),
+                Severity::Error,
+                "",
+            );
+            self.builder.start_node(SyntaxKind::Error);
+            self.bump(SyntaxKind::Whitespace, start_idx);
+            self.bump_lines(SyntaxKind::Text, brace_idx + 1);
+            self.builder.finish_node();
+            if self.idx >= self.line_end_idx() {
+                self.bump_newline();
             }
+            return true;
         };
 
-        if missing_space {
-            self.push_diag(
-                header_span,
-                Severity::Warning,
-                "LMM 格式规范：标记名称与左大括号之间必须有一个空格。建议改为 '@name {'",
-            );
+        let name = &header_raw[1..1 + tokens[1].1];
+        let raw = is_raw_block(name);
+        let plus_count = tokens
+            .iter()
+            .find(|(kind, _)| *kind == SyntaxKind::Plus)
+            .map_or(0, |(_, len)| *len);
+
+        let indent = &line[..at_col];
+        let header_start = self.position_at(start_idx);
+        self.builder.start_node(SyntaxKind::Block);
+        self.builder.start_node(SyntaxKind::Header);
+        self.bump(SyntaxKind::Whitespace, start_idx);
+        for (kind, len) in tokens {
+            self.bump(kind, self.idx + len);
+        }
+        self.bump(SyntaxKind::LBrace, brace_idx + 1);
+        self.builder.finish_node();
+        let header = Span::new(header_start, self.pos);
+        // Blank space after the `{` ends the header line like a newline does.
+        let line_end = self.line_end_idx();
+        if self.input[self.idx..line_end].trim().is_empty() {
+            self.bump(SyntaxKind::Whitespace, line_end);
+            self.bump_newline();
         }
 
-        Some(BlockHeader {
-            name,
-            args,
-            params: params
-                .into_iter()
-                .map(|(key, value)| Attribute {
-                    key,
-                    value,
-                    span: header_span,
-                })
-                .collect(),
-            plus_count,
-            span: Span::new(start_pos, header_span.end),
-        })
+        self.parse_attributes_at_start();
+        if raw {
+            self.raw_depth += 1;
+        }
+        let close = block_close_delim(plus_count);
+        let closer = || Closer {
+            delim: &close,
+            indent: at_col,
+        };
+        let checkpoint = (
+            self.builder.checkpoint(),
+            self.idx,
+            self.line_start_idx,
+            self.pos,
+            self.diagnostics.len(),
+        );
+        self.depth += 1;
+        let mut until = self.parse_nodes_until(Some(closer()));
+        // Reaching the end of input leaves every open block unclosed, so the
+        // outermost one parses its content again with recovery. Inner blocks
+        // are parsed again as part of it.
+        if matches!(until, Until::Open { .. }) && !self.recovering && self.depth == 1 {
+            let (elements, idx, line_start_idx, pos, diagnostics) = checkpoint;
+            self.builder.rollback(elements);
+            self.idx = idx;
+            self.line_start_idx = line_start_idx;
+            self.pos = pos;
+            self.diagnostics.truncate(diagnostics);
+            self.recovering = true;
+            until = self.parse_nodes_until(Some(closer()));
+            self.recovering = false;
+        }
+        self.depth -= 1;
+        if let Until::Open { near_miss } = until {
+            let mut diagnostic = Diagnostic::new(
+                DiagnosticCode::UnclosedBlock,
+                Severity::Error,
+                header,
+                self.message(DiagnosticCode::UnclosedBlock, "", &[("closer", &close)]),
+            );
+            if let Some(near_miss) = near_miss {
+                let note = self.message(
+                    DiagnosticCode::UnclosedBlock,
+                    "near-miss",
+                    &[("found", &near_miss.closer), ("closer", &close)],
+                );
+                diagnostic = diagnostic.with_related(near_miss.span, note);
+            }
+            let edit = if self.at_end() {
+                self.insert_line_at_end(&format!("{indent}{close}"))
+            } else {
+                Edit::insert(self.pos, format!("{indent}{close}\n"))
+            };
+            let diagnostic = diagnostic.with_fix(
+                self.message(DiagnosticCode::UnclosedBlock, "fix", &[("closer", &close)]),
+                vec![edit],
+            );
+            self.diagnostics.push(diagnostic);
+        }
+        if raw {
+            self.raw_depth -= 1;
+        }
+        self.builder.finish_node();
+        true
     }
 
-    fn scan_header(&self, start_idx: usize, start_pos: Position) -> Option<(String, Span, usize)> {
-        let mut header = String::new();
-        let mut idx = start_idx;
-        let mut pos = start_pos;
-
-        while idx < self.input.len() {
-            let slice = &self.input[idx..];
-            let Some(ch) = slice.chars().next() else {
-                break;
-            };
-            if ch == '{' {
-                let end_pos = advance_position(pos, ch);
-                let end_idx = idx + ch.len_utf8();
-                return Some((header, Span::new(start_pos, end_pos), end_idx));
-            }
-            if ch == '\n' {
-                header.push(' ');
-                pos = Position::new(pos.line + 1, 0, 0, 0);
-                idx += ch.len_utf8();
-                continue;
-            }
-            header.push(ch);
-            pos = advance_position(pos, ch);
-            idx += ch.len_utf8();
+    fn find_close_in_line(&self, close: &str) -> Option<usize> {
+        let line_end = self.line_end_idx();
+        if self.idx > line_end {
+            return None;
         }
-        None
+        let line_tail = &self.input[self.idx..line_end];
+        let found = if self.raw_depth > 0 {
+            line_tail.find(close)
+        } else {
+            find_inline_close(line_tail, close)
+        };
+        found.map(|idx| self.idx + idx)
     }
 
-    fn collect_until_dollar(&mut self) -> Vec<(usize, &'a str)> {
-        let mut out = Vec::new();
-        while !self.at_end() {
-            let line = self.current_line_slice().unwrap_or("");
-            if is_dollar_line(line) {
-                self.advance_line();
-                return out;
-            }
-            out.push((self.pos.line, line));
-            self.advance_line();
+    /// Finds a closer ending the current line that has the wrong number of
+    /// `+` signs for `close`, such as `}+` where `}++` is expected.
+    fn find_near_miss(&self, close: &str) -> Option<NearMiss> {
+        let line_tail = &self.input[self.idx..self.line_end_idx()];
+        let brace = if self.raw_depth > 0 {
+            line_tail.trim_end().rfind('}')?
+        } else {
+            find_inline_close(line_tail, "}")?
+        };
+        let found = line_tail[brace..].trim_end();
+        if found == close || found.len() == 1 || !found[1..].bytes().all(|b| b == b'+') {
+            return None;
         }
-        let line_index = self.pos.line.saturating_sub(1);
-        let span = span_at_line_start(line_index);
-        self.push_diag(span, Severity::Error, "unterminated $ block");
-        out
+        let start = self.idx + brace;
+        Some(NearMiss {
+            span: Span::new(
+                self.position_at(start),
+                self.position_at(start + found.len()),
+            ),
+            closer: found.to_string(),
+        })
     }
 
-    fn consume_trailing_comments(&mut self) {
-        while !self.at_end() {
-            let Some(line) = self.current_line_slice() else {
-                break;
-            };
-            if is_comment_line(line) || line.trim().is_empty() {
-                self.advance_line();
-                continue;
+    /// Returns whether the cursor sits on one of the sync points.
+    fn reached_sync_point(&mut self) -> bool {
+        if !self.is_line_start() {
+            return false;
+        }
+        match self.sync_points.binary_search(&self.idx) {
+            Ok(index) => {
+                self.synced_at = Some(index);
+                true
             }
-            break;
+            Err(_) => false,
         }
     }
 
-    fn find_close_in_line(&self, close: &str) -> Option<usize> {
-        let line_end = self.line_end_idx();
-        if self.idx > line_end {
-            return None;
+    /// Line of the last character in the input, once parsing reached the end.
+    fn last_line(&self) -> usize {
+        if self.input.ends_with('\n') {
+            self.pos.line.saturating_sub(1)
+        } else {
+            self.pos.line
         }
-        let line_tail = &self.input[self.idx..line_end];
-        line_tail.find(close).map(|idx| self.idx + idx)
     }
 
     fn is_line_start(&self) -> bool {
@@ -388,49 +625,51 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn current_line_offset(&self) -> usize {
-        self.idx.saturating_sub(self.line_start_idx)
+    /// Emits `input[idx..end]` as one token and advances past it.
+    fn bump(&mut self, kind: SyntaxKind, end: usize) {
+        if end <= self.idx {
+            return;
+        }
+        self.builder.token(kind, &self.input[self.idx..end]);
+        self.advance_to_idx(end);
     }
 
-    fn advance_line(&mut self) {
-        let line_end = self.line_end_idx();
-        if self.idx < line_end {
-            self.advance_to_idx(line_end);
-        }
-        if self.peek_char() == Some('\n') {
-            self.advance_char();
-        }
+    /// Emits a token with its surrounding whitespace split off.
+    fn bump_trimmed(&mut self, kind: SyntaxKind, end: usize) {
+        let text = &self.input[self.idx..end];
+        let leading = text.len() - text.trim_start().len();
+        let content = text.trim().len();
+        let start = self.idx;
+        self.bump(SyntaxKind::Whitespace, start + leading);
+        self.bump(kind, start + leading + content);
+        self.bump(SyntaxKind::Whitespace, end);
     }
 
-    fn advance_line_if_eol(&mut self) {
-        let line_end = self.line_end_idx();
-        if self.idx >= line_end {
-            if self.peek_char() == Some('\n') {
-                self.advance_char();
+    /// Emits `kind` tokens up to `end`, splitting out each newline.
+    fn bump_lines(&mut self, kind: SyntaxKind, end: usize) {
+        while self.idx < end {
+            match self.input[self.idx..end].find('\n') {
+                Some(offset) => {
+                    self.bump(kind, self.idx + offset);
+                    self.bump_newline();
+                }
+                None => self.bump(kind, end),
             }
         }
     }
 
-    fn peek_char(&self) -> Option<char> {
-        self.input[self.idx..].chars().next()
+    fn bump_newline(&mut self) {
+        if self.input[self.idx..].starts_with('\n') {
+            self.bump(SyntaxKind::Newline, self.idx + 1);
+        }
     }
 
-    fn advance_char(&mut self) -> Option<char> {
-        let ch = self.peek_char()?;
-        let len = ch.len_utf8();
-        self.idx += len;
-        if ch == '\n' {
-            self.pos.line += 1;
-            self.pos.col8 = 0;
-            self.pos.col16 = 0;
-            self.pos.col32 = 0;
-            self.line_start_idx = self.idx;
-        } else {
-            self.pos.col8 += len;
-            self.pos.col16 += ch.len_utf16();
-            self.pos.col32 += 1;
+    fn position_at(&self, target: usize) -> Position {
+        let mut pos = self.pos;
+        for ch in self.input[self.idx..target].chars() {
+            pos = advance_position(pos, ch);
         }
-        Some(ch)
+        pos
     }
 
     fn advance_to_idx(&mut self, target: usize) {
@@ -455,31 +694,30 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn push_diag(&mut self, span: Span, severity: Severity, message: &str) {
-        self.diagnostics.push(Diagnostic {
-            span,
-            severity,
-            message: message.into(),
-        });
+    /// Edit adding `line` as the new last line of the input, once parsing
+    /// reached the end.
+    fn insert_line_at_end(&self, line: &str) -> Edit {
+        let new_text = if self.input.is_empty() || self.input.ends_with('\n') {
+            format!("{line}\n")
+        } else {
+            format!("\n{line}")
+        };
+        Edit::insert(self.pos, new_text)
     }
-}
 
-struct BlockHeader {
-    name: SmolStr,
-    args: Vec<SmolStr>,
-    params: Vec<Attribute>,
-    plus_count: usize,
-    span: Span,
-}
+    /// Pushes the message of `code` selected by `key`.
+    fn push_diag(&mut self, code: DiagnosticCode, span: Span, severity: Severity, key: &str) {
+        let message = self.message(code, key, &[]);
+        self.diagnostics
+            .push(Diagnostic::new(code, severity, span, message));
+    }
 
-struct LineBuf {
-    indent: usize,
-    value: String,
-    span: Span,
-    is_comment: bool,
+    fn message(&self, code: DiagnosticCode, key: &str, args: &[(&str, &str)]) -> String {
+        message(self.locale, code, key, args)
+    }
 }
 
-fn is_comment_line(line: &str) -> bool {
+pub(crate) fn is_comment_line(line: &str) -> bool {
     let trimmed = line.trim_start();
     if trimmed.starts_with("!!") {
         return false;
@@ -487,7 +725,18 @@ fn is_comment_line(line: &str) -> bool {
     trimmed.starts_with('!')
 }
 
-fn is_dollar_line(line: &str) -> bool {
+/// Returns the key of `line` if it reads as a `#key: value` attribute.
+pub(crate) fn attribute_key(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("##") {
+        return None;
+    }
+    let (key, _) = trimmed.strip_prefix('#')?.split_once(':')?;
+    let key = key.trim();
+    (!key.is_empty()).then_some(key)
+}
+
+pub(crate) fn is_dollar_line(line: &str) -> bool {
     line.trim() == "$"
 }
 
@@ -499,255 +748,114 @@ fn block_close_delim(plus_count: usize) -> String {
     out
 }
 
-fn parse_text_line(line: &str, line_index: usize, options: ParseOptions) -> Option<LineBuf> {
-    if is_comment_line(line) {
-        return parse_comment_line(line, line_index, options);
-    }
-    parse_text_segment(line, line_index, 0, line.len(), options)
-}
-
-fn parse_text_segment(
-    line: &str,
-    line_index: usize,
-    start: usize,
-    end: usize,
-    options: ParseOptions,
-) -> Option<LineBuf> {
-    if start >= end {
-        return None;
-    }
-    let segment = &line[start..end];
-    let mut indent = 0usize;
-    let mut skip = 0usize;
-
-    if start == 0 {
-        for ch in segment.chars() {
-            if ch == ' ' {
-                indent += options.space_width;
-                skip += ch.len_utf8();
-            } else if ch == '\t' {
-                indent += options.tab_width;
-                skip += ch.len_utf8();
-            } else {
-                break;
-            }
-        }
-    }
-
-    let value_start = start + skip;
-    let mut value = segment[skip..].to_string();
-    if start == 0 && value.starts_with("!!") {
-        value = format!("!{}", &value[2..]);
-    }
-
-    if value.is_empty() {
-        return None;
-    }
-
-    let span = span_for_line_offsets(line_index, line, value_start, value_start + value.len());
-
-    Some(LineBuf {
-        indent,
-        value,
-        span,
-        is_comment: false,
-    })
+fn leading_ws_len(text: &str) -> usize {
+    text.len() - text.trim_start_matches([' ', '\t']).len()
 }
 
-fn parse_comment_line(line: &str, line_index: usize, options: ParseOptions) -> Option<LineBuf> {
-    if !is_comment_line(line) {
-        return None;
-    }
-    let mut indent = 0usize;
-    let mut skip = 0usize;
-    for ch in line.chars() {
-        if ch == ' ' {
-            indent += options.space_width;
-            skip += ch.len_utf8();
-        } else if ch == '\t' {
-            indent += options.tab_width;
-            skip += ch.len_utf8();
-        } else {
-            break;
-        }
-    }
-    let after_indent = &line[skip..];
-    let rest = after_indent.strip_prefix('!').unwrap_or("");
-    let trimmed = rest.trim_start();
-    let leading = rest.len().saturating_sub(trimmed.len());
-    let value_start = skip + 1 + leading;
-    let value = trimmed.to_string();
-    let span = span_for_line_offsets(line_index, line, value_start, value_start + value.len());
-    Some(LineBuf {
-        indent,
-        value,
-        span,
-        is_comment: true,
-    })
-}
-
-fn finalize_text(lines: Vec<LineBuf>) -> Text {
-    let mut out = Vec::with_capacity(lines.len());
-    for line in lines {
-        let value = unescape_text(&line.value);
-        out.push(TextLine {
-            indent: line.indent,
-            value: value.into(),
-            span: line.span,
-            is_comment: line.is_comment,
-        });
-    }
-    Text { lines: out }
+/// Returns whether `line` opens a block at an indentation of at most `indent`
+/// bytes, which ends any block opened at `indent` that is still open.
+fn is_outer_header(line: &str, indent: usize) -> bool {
+    let Some(at_col) = find_block_header_start(line) else {
+        return false;
+    };
+    let header = &line[at_col..];
+    at_col <= indent
+        && header.contains('{')
+        && marker_name(header).is_some_and(|name| !is_inline_marker(name))
 }
 
-fn unescape_text(input: &str) -> String {
-    let mut out = String::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(ch) = chars.next() {
-        if let Some(next) = chars.peek().copied() {
-            if ch == '@' && next == '@' {
-                out.push('@');
-                chars.next();
-                continue;
-            }
-            if ch == '#' && next == '#' {
-                out.push('#');
-                chars.next();
-                continue;
-            }
-            if ch == '{' && next == '{' {
-                out.push('{');
-                chars.next();
-                continue;
-            }
-        }
-        out.push(ch);
+fn find_block_header_start(line: &str) -> Option<usize> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('@') && !trimmed.starts_with("@@") {
+        return Some(line.len() - trimmed.len());
     }
-
-    out
+    None
 }
 
-fn find_block_header_start(line: &str) -> Option<(usize, usize)> {
-    let trimmed = line.trim_start();
-    if trimmed.starts_with('@') {
-        let at_col = line.len() - trimmed.len();
-        return Some((at_col, at_col));
+/// Finds the `{` that opens a block, given the text from its `@`.
+///
+/// A run of two or more braces right after other header text, as in
+/// `@code lang{{x {`, is an escape inside an argument rather than the opener.
+fn find_header_brace(text: &str) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(offset) = text[idx..].find('{') {
+        let brace = idx + offset;
+        let run = text[brace..].bytes().take_while(|b| *b == b'{').count();
+        let in_arg = text[..brace]
+            .bytes()
+            .next_back()
+            .is_some_and(|b| !b.is_ascii_whitespace());
+        if run < 2 || !in_arg {
+            return Some(brace);
+        }
+        idx = brace + run;
     }
     None
 }
 
-fn parse_header_parts(
-    header: &str,
-) -> Option<(SmolStr, Vec<SmolStr>, Vec<(SmolStr, SmolStr)>, usize, bool)> {
+/// Splits a block header (without its `{`) into `(kind, byte length)` tokens.
+///
+/// Returns `None` when the header has no block name.
+fn tokenize_header(header: &str) -> Option<Vec<(SyntaxKind, usize)>> {
     let bytes = header.as_bytes();
-    if bytes.is_empty() || bytes[0] as char != '@' {
-        return None;
-    }
-    let mut cursor = 1;
-    let name_start = cursor;
-    while cursor < bytes.len() {
-        let ch = bytes[cursor] as char;
-        if ch.is_ascii_alphanumeric() || ch == '_' || ch == '-' {
-            cursor += 1;
-        } else {
-            break;
-        }
-    }
-    if cursor == name_start {
+    let name_len = bytes[1..]
+        .iter()
+        .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_' || **b == b'-')
+        .count();
+    if name_len == 0 {
         return None;
     }
-    let name = &header[name_start..cursor];
-    let missing_space = cursor == bytes.len();
+    let mut tokens = vec![(SyntaxKind::At, 1), (SyntaxKind::Name, name_len)];
+    let mut cursor = 1 + name_len;
+    let mut seen_params = false;
+    let mut seen_plus = false;
 
-    cursor = skip_spaces(bytes, cursor);
-    let mut args = Vec::new();
     while cursor < bytes.len() {
-        let ch = bytes[cursor] as char;
-        if ch == '[' || ch == '+' {
-            break;
-        }
-        if ch.is_ascii_whitespace() {
-            cursor = skip_spaces(bytes, cursor);
-            continue;
-        }
         let start = cursor;
-        while cursor < bytes.len() {
-            let ch = bytes[cursor] as char;
-            if ch.is_ascii_whitespace() || ch == '[' || ch == '+' {
-                break;
-            }
+        let ch = bytes[cursor];
+        let kind = if ch == b'\n' {
             cursor += 1;
-        }
-        let arg = &header[start..cursor];
-        args.push(arg.into());
-        cursor = skip_spaces(bytes, cursor);
-    }
-
-    let mut params = Vec::new();
-    if cursor < bytes.len() && bytes[cursor] as char == '[' {
-        let (parsed, next) = parse_params(header, cursor);
-        params = parsed;
-        cursor = next;
-        cursor = skip_spaces(bytes, cursor);
-    }
-
-    let mut plus_count = 0usize;
-    while cursor < bytes.len() && bytes[cursor] as char == '+' {
-        plus_count += 1;
-        cursor += 1;
-    }
-
-    Some((name.into(), args, params, plus_count, missing_space))
-}
-
-fn parse_params(header: &str, start: usize) -> (Vec<(SmolStr, SmolStr)>, usize) {
-    let bytes = header.as_bytes();
-    let mut cursor = start;
-    if bytes.get(cursor).map(|b| *b as char) != Some('[') {
-        return (Vec::new(), cursor);
-    }
-    cursor += 1;
-    let mut params = Vec::new();
-    let mut token = String::new();
-
-    while cursor < bytes.len() {
-        let ch = bytes[cursor] as char;
-        if ch == ']' {
-            if !token.trim().is_empty() {
-                params.push(parse_param_token(&token));
+            SyntaxKind::Newline
+        } else if ch.is_ascii_whitespace() {
+            while cursor < bytes.len()
+                && bytes[cursor] != b'\n'
+                && bytes[cursor].is_ascii_whitespace()
+            {
+                cursor += 1;
             }
-            cursor += 1;
-            return (params, cursor);
-        }
-        if ch == ',' {
-            if !token.trim().is_empty() {
-                params.push(parse_param_token(&token));
+            SyntaxKind::Whitespace
+        } else if ch == b'+' && !seen_plus {
+            while cursor < bytes.len() && bytes[cursor] == b'+' {
+                cursor += 1;
             }
-            token.clear();
-            cursor += 1;
-            continue;
-        }
-        token.push(ch);
-        cursor += 1;
-    }
-
-    (params, cursor)
-}
-
-fn parse_param_token(token: &str) -> (SmolStr, SmolStr) {
-    let token = token.trim();
-    let Some((key, value)) = token.split_once('=') else {
-        return (token.into(), "".into());
-    };
-    (key.trim().into(), value.trim().into())
-}
-
-fn skip_spaces(bytes: &[u8], mut index: usize) -> usize {
-    while index < bytes.len() && (bytes[index] as char).is_ascii_whitespace() {
-        index += 1;
+            seen_plus = true;
+            SyntaxKind::Plus
+        } else if ch == b'[' && !seen_params && !seen_plus {
+            cursor = header[cursor..]
+                .find(']')
+                .map_or(bytes.len(), |end| cursor + end + 1);
+            seen_params = true;
+            SyntaxKind::Params
+        } else {
+            while cursor < bytes.len()
+                && !bytes[cursor].is_ascii_whitespace()
+                && bytes[cursor] != b'['
+                && bytes[cursor] != b'+'
+            {
+                cursor += 1;
+            }
+            if cursor == start {
+                cursor += 1;
+            }
+            if seen_params || seen_plus {
+                SyntaxKind::Unknown
+            } else {
+                SyntaxKind::Arg
+            }
+        };
+        tokens.push((kind, cursor - start));
     }
-    index
+    Some(tokens)
 }
 
 fn span_at_line_start(line_index: usize) -> Span {
@@ -760,13 +868,22 @@ fn line_span_from_line(line_index: usize, line: &str) -> Span {
     Span::new(Position::new(line_index, 0, 0, 0), end)
 }
 
-fn span_for_line_offsets(line_index: usize, line: &str, start: usize, end: usize) -> Span {
+pub(crate) fn span_for_line_offsets(
+    line_index: usize,
+    line: &str,
+    start: usize,
+    end: usize,
+) -> Span {
     let start_pos = position_for_line_offset(line_index, line, start);
     let end_pos = position_for_line_offset(line_index, line, end);
     Span::new(start_pos, end_pos)
 }
 
-fn position_for_line_offset(line_index: usize, line: &str, byte_offset: usize) -> Position {
+pub(crate) fn position_for_line_offset(
+    line_index: usize,
+    line: &str,
+    byte_offset: usize,
+) -> Position {
     let mut col8 = 0usize;
     let mut col16 = 0usize;
     let mut col32 = 0usize;
@@ -779,7 +896,7 @@ fn position_for_line_offset(line_index: usize, line: &str, byte_offset: usize) -
     Position::new(line_index, col8, col16, col32)
 }
 
-fn advance_position(pos: Position, ch: char) -> Position {
+pub(crate) fn advance_position(pos: Position, ch: char) -> Position {
     if ch == '\n' {
         Position::new(pos.line + 1, 0, 0, 0)
     } else {
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::anchor::check_references;
+use crate::ast::{Block, Diagnostic, DiagnosticCode, Inline, Node, Span, TextChange};
+use crate::lower::{Reuse, lower_document};
+use crate::parser::{ParseOptions, ParseResult, parse_document_with_options, parse_partial};
+use crate::syntax::{GreenElement, SyntaxKind, SyntaxNode, TreeBuilder};
+
+/// Re-parses `input`, the text of `previous` after `change` was applied.
+pub fn reparse_document(previous: ParseResult, input: &str, change: TextChange) -> ParseResult {
+    reparse_document_with_options(previous, input, change, ParseOptions::default())
+}
+
+/// Re-parses `input`, the text of `previous` after `change` was applied.
+///
+/// Only the top-level items touched by the change are parsed again. Items
+/// before it are reused as they are, and items after it are reused as soon as
+/// the parser reaches one of their line starts, with spans moved by the
+/// number of lines the change added or removed.
+pub fn reparse_document_with_options(
+    previous: ParseResult,
+    input: &str,
+    change: TextChange,
+    options: ParseOptions,
+) -> ParseResult {
+    let old = previous.syntax.green();
+    let removed = change.end.saturating_sub(change.start);
+    if change.start > change.end
+        || change.end > old.len()
+        || old.len() - removed + change.inserted != input.len()
+    {
+        return parse_document_with_options(input, options);
+    }
+
+    let children = old.children();
+    let mut starts = Vec::with_capacity(children.len() + 1);
+    let mut offset = 0;
+    for child in children {
+        starts.push(offset);
+        offset += child.len();
+    }
+    starts.push(offset);
+    let is_line_start =
+        |index: usize| index == 0 || children[index - 1].kind() == SyntaxKind::Newline;
+
+    // The attribute zone and the lookahead of a header missing its `{` both
+    // depend on later lines, so reparsing never starts inside or after them.
+    let attr_end = children
+        .iter()
+        .rposition(|child| child.kind() == SyntaxKind::AttrLine)
+        .map_or(0, |index| match children.get(index + 1) {
+            Some(next) if next.kind() == SyntaxKind::Newline => starts[index + 2],
+            _ => starts[index + 1],
+        });
+    let before = starts.partition_point(|&offset| offset <= change.start) - 1;
+    let limit = children[..before]
+        .iter()
+        .position(contains_error)
+        .unwrap_or(before);
+    let first = (0..=limit)
+        .rev()
+        .find(|&index| is_line_start(index))
+        .filter(|&index| starts[index] > attr_end)
+        .unwrap_or(0);
+    let start = starts[first];
+
+    let resume: Vec<usize> = (first + 1..children.len())
+        .filter(|&index| {
+            is_line_start(index) && starts[index] >= change.end && starts[index] > attr_end
+        })
+        .collect();
+    let sync_points: Vec<usize> = resume
+        .iter()
+        .map(|&index| starts[index] - change.end + change.start + change.inserted)
+        .collect();
+
+    let line = input[..start].matches('\n').count();
+    let mut builder = TreeBuilder::new();
+    builder.start_node(SyntaxKind::Document);
+    for child in &children[..first] {
+        builder.push(child.clone());
+    }
+    let mut partial = parse_partial(input, builder, start, line, &sync_points, options.locale);
+    let rest = partial
+        .synced_at
+        .map_or(children.len(), |index| resume[index]);
+    for child in &children[rest..] {
+        partial.builder.push(child.clone());
+    }
+    partial.builder.finish_node();
+    let syntax = SyntaxNode::new_root(partial.builder.finish());
+
+    let old_rest_line = line
+        + children[first..rest]
+            .iter()
+            .map(newline_count)
+            .sum::<usize>();
+    let delta = partial.end_line as isize - old_rest_line as isize;
+    let mut diagnostics = partial.diagnostics;
+    // References are checked against the whole document again below.
+    let previous_diagnostics = previous
+        .diagnostics
+        .into_iter()
+        .filter(|diag| !is_reference_check(diag.code));
+    for mut diag in previous_diagnostics {
+        if diag.span.start.line < line {
+            diagnostics.push(diag);
+        } else if diag.span.start.line >= old_rest_line && rest < children.len() {
+            shift_diagnostic(&mut diag, old_rest_line, delta);
+            diagnostics.push(diag);
+        }
+    }
+
+    // `previous.syntax` keeps the old green nodes alive, so pointers only match reused blocks.
+    let old_blocks = children.iter().filter_map(|child| match child {
+        GreenElement::Node(node) if node.kind() == SyntaxKind::Block => Some(Arc::as_ptr(node)),
+        _ => None,
+    });
+    let blocks = old_blocks
+        .zip(
+            previous
+                .document
+                .nodes
+                .into_iter()
+                .filter_map(|node| match node {
+                    Node::Block(block) => Some(block),
+                    Node::Text(_) | Node::Region(_) => None,
+                }),
+        )
+        .collect::<HashMap<_, _>>();
+    let reuse = Reuse {
+        fresh: start..partial.end,
+        blocks,
+    };
+    let document = lower_document(&syntax, input, options, &mut diagnostics, reuse);
+    if options.validate {
+        diagnostics.extend(check_references(&document, options.locale));
+    }
+    diagnostics.sort_by_key(|diag| (diag.span.start.line, diag.span.start.col8));
+    ParseResult {
+        syntax,
+        document,
+        diagnostics,
+    }
+}
+
+fn is_reference_check(code: DiagnosticCode) -> bool {
+    matches!(
+        code,
+        DiagnosticCode::DanglingReference | DiagnosticCode::DuplicateId
+    )
+}
+
+/// Returns whether `element` holds an error node or an unclosed block, whose
+/// extent depends on the text after it.
+fn contains_error(element: &GreenElement) -> bool {
+    match element {
+        GreenElement::Node(node) => {
+            let unclosed = node.kind() == SyntaxKind::Block
+                && !node
+                    .children()
+                    .iter()
+                    .any(|child| child.kind() == SyntaxKind::Close);
+            node.kind() == SyntaxKind::Error
+                || unclosed
+                || node.children().iter().any(contains_error)
+        }
+        GreenElement::Token(_) => false,
+    }
+}
+
+fn newline_count(element: &GreenElement) -> usize {
+    match element {
+        GreenElement::Node(node) => node.children().iter().map(newline_count).sum(),
+        GreenElement::Token(token) => token.text().matches('\n').count(),
+    }
+}
+
+/// Moves every span in `block` by `delta` lines.
+pub(crate) fn shift_block(block: &mut Block, delta: isize) {
+    shift_span(&mut block.span, delta);
+    if let Some(span) = &mut block.close_span {
+        shift_span(span, delta);
+    }
+    for attr in block.params.iter_mut().chain(&mut block.attrs) {
+        shift_span(&mut attr.span, delta);
+    }
+    shift_nodes(&mut block.nodes, delta);
+}
+
+fn shift_nodes(nodes: &mut [Node], delta: isize) {
+    for node in nodes {
+        match node {
+            Node::Block(block) => shift_block(block, delta),
+            Node::Text(text) => {
+                for line in &mut text.lines {
+                    shift_span(&mut line.span, delta);
+                    shift_inlines(&mut line.inlines, delta);
+                }
+            }
+            Node::Region(region) => {
+                shift_span(&mut region.span, delta);
+                if let Some(span) = &mut region.close_span {
+                    shift_span(span, delta);
+                }
+                shift_nodes(&mut region.nodes, delta);
+            }
+        }
+    }
+}
+
+/// Moves the spans of `diag` that start at or after line `from` by `delta`
+/// lines. Related spans and fix edits may point at earlier lines, such as the
+/// attribute zone, which stay where they are.
+fn shift_diagnostic(diag: &mut Diagnostic, from: usize, delta: isize) {
+    let spans = std::iter::once(&mut diag.span)
+        .chain(diag.related.iter_mut().map(|related| &mut related.span))
+        .chain(
+            diag.fixes
+                .iter_mut()
+                .flat_map(|fix| &mut fix.edits)
+                .map(|edit| &mut edit.span),
+        );
+    for span in spans.filter(|span| span.start.line >= from) {
+        shift_span(span, delta);
+    }
+}
+
+fn shift_inlines(inlines: &mut [Inline], delta: isize) {
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => shift_span(&mut text.span, delta),
+            Inline::Code(code) => shift_span(&mut code.span, delta),
+            Inline::Marker(marker) => {
+                shift_span(&mut marker.span, delta);
+                shift_inlines(&mut marker.nodes, delta);
+            }
+        }
+    }
+}
+
+fn shift_span(span: &mut Span, delta: isize) {
+    span.start.line = span.start.line.saturating_add_signed(delta);
+    span.end.line = span.end.line.saturating_add_signed(delta);
+}
@@ -0,0 +1,545 @@
+use data_classes::derive::*;
+
+use crate::ast::{Block, Diagnostic, DiagnosticCode, Document, Inline, Node, Severity};
+use crate::messages::{Locale, message};
+
+/// Whether a name is used as a block or as an inline marker.
+#[data(copy)]
+pub enum BlockKind {
+    /// `@name {` at the start of a line, containing lines and nested blocks.
+    Block,
+    /// `@name {...}` inside a line of text.
+    Inline,
+}
+
+/// How the renderers present a built-in block or marker.
+#[data(copy)]
+pub enum Render {
+    /// A section with a heading built from the arguments.
+    Section,
+    /// A bullet or line list, one item per line.
+    List,
+    /// Verbatim code.
+    Code,
+    /// A generic container.
+    Container,
+    Bold,
+    Italic,
+    Underline,
+    Link,
+    /// A link to the block whose id is the argument.
+    Ref,
+    /// A table of contents listing the sections of the document.
+    Toc,
+}
+
+/// Which positional arguments a block or marker takes.
+#[data(copy)]
+pub enum Args {
+    None,
+    /// Free text, such as a `part` title.
+    Free,
+    /// Flag params written without brackets, such as `@list bullet {`.
+    Flags,
+    /// Exactly one argument, such as the URL of `@link(url)`.
+    Required,
+}
+
+/// Values a parameter accepts.
+#[data(copy)]
+pub enum ParamValue {
+    /// A bare flag such as `bullet`, written without `=`.
+    Flag,
+    /// Free text; the listed values are only suggestions.
+    Text(&'static [&'static str]),
+    /// Exactly one of the listed values.
+    Enum(&'static [&'static str]),
+}
+
+/// A `[name=value]` parameter of a block.
+#[data(copy)]
+pub struct ParamSchema {
+    pub name: &'static str,
+    pub value: ParamValue,
+    pub doc: &'static str,
+}
+
+/// A `#key: value` attribute.
+#[data(copy)]
+pub struct AttrSchema {
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub required: bool,
+}
+
+/// Which nested blocks a block may contain.
+#[data(copy)]
+pub enum Children {
+    /// Any block.
+    Any,
+    /// Only the listed blocks.
+    Only(&'static [&'static str]),
+    /// Verbatim content; nothing inside is interpreted.
+    Raw,
+}
+
+/// Declarative description of a block or inline marker.
+#[data(copy)]
+pub struct BlockSchema {
+    pub name: &'static str,
+    pub kind: BlockKind,
+    pub render: Render,
+    /// One-line description, e.g. for completion lists.
+    pub summary: &'static str,
+    /// Longer documentation, e.g. for hovers.
+    pub doc: &'static str,
+    pub args: Args,
+    pub params: &'static [ParamSchema],
+    /// Groups of params of which at most one may be given.
+    pub exclusive: &'static [&'static [&'static str]],
+    /// Attributes specific to this block, in addition to [`BLOCK_ATTRS`].
+    pub attrs: &'static [AttrSchema],
+    pub children: Children,
+}
+
+/// Language ids suggested for `code[lang=...]`.
+pub const CODE_LANGUAGES: &[&str] = &[
+    "lumosmark",
+    "text",
+    "bash",
+    "c",
+    "cpp",
+    "css",
+    "go",
+    "html",
+    "java",
+    "javascript",
+    "json",
+    "markdown",
+    "python",
+    "rust",
+    "toml",
+    "typescript",
+    "yaml",
+];
+
+/// Attributes understood at the top of a document.
+pub const DOCUMENT_ATTRS: &[AttrSchema] = &[
+    AttrSchema {
+        name: "title",
+        doc: "Document title.",
+        required: false,
+    },
+    AttrSchema {
+        name: "author",
+        doc: "Document author.",
+        required: false,
+    },
+    AttrSchema {
+        name: "version",
+        doc: "Document version.",
+        required: false,
+    },
+];
+
+/// Attributes understood on every block.
+pub const BLOCK_ATTRS: &[AttrSchema] = &[AttrSchema {
+    name: "id",
+    doc: "Identifier of the block.",
+    required: false,
+}];
+
+/// Schemas of the built-in blocks and inline markers.
+pub const BUILTIN_BLOCKS: &[BlockSchema] = &[
+    BlockSchema {
+        name: "part",
+        kind: BlockKind::Block,
+        render: Render::Section,
+        summary: "Section with a heading",
+        doc: "Defines a section. Its arguments become the heading, and nested parts \
+              render one heading level deeper.",
+        args: Args::Free,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "list",
+        kind: BlockKind::Block,
+        render: Render::List,
+        summary: "List with one item per line",
+        doc: "A list with one item per line. `bullet` renders bullet points and `line` \
+              renders plain lines; bullets are the default.",
+        args: Args::Flags,
+        params: &[
+            ParamSchema {
+                name: "bullet",
+                value: ParamValue::Flag,
+                doc: "Render items as bullet points.",
+            },
+            ParamSchema {
+                name: "line",
+                value: ParamValue::Flag,
+                doc: "Render items as plain lines.",
+            },
+        ],
+        exclusive: &[&["bullet", "line"]],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "code",
+        kind: BlockKind::Block,
+        render: Render::Code,
+        summary: "Verbatim code block",
+        doc: "A code block whose content is kept verbatim. Set the language with \
+              `[lang=...]`.",
+        args: Args::None,
+        params: &[ParamSchema {
+            name: "lang",
+            value: ParamValue::Text(CODE_LANGUAGES),
+            doc: "Language of the code.",
+        }],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Raw,
+    },
+    BlockSchema {
+        name: "toc",
+        kind: BlockKind::Block,
+        render: Render::Toc,
+        summary: "Table of contents",
+        doc: "Lists every section of the document as nested links to its heading. \
+              Limit how deep the list goes with `[depth=...]`; its content is ignored.",
+        args: Args::None,
+        params: &[ParamSchema {
+            name: "depth",
+            value: ParamValue::Enum(&["1", "2", "3", "4", "5", "6"]),
+            doc: "Deepest section level listed; all levels by default.",
+        }],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Only(&[]),
+    },
+    BlockSchema {
+        name: "node",
+        kind: BlockKind::Block,
+        render: Render::Container,
+        summary: "Generic container",
+        doc: "A generic container block.",
+        args: Args::None,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "b",
+        kind: BlockKind::Inline,
+        render: Render::Bold,
+        summary: "Bold text",
+        doc: "Renders its content in bold.",
+        args: Args::None,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "i",
+        kind: BlockKind::Inline,
+        render: Render::Italic,
+        summary: "Italic text",
+        doc: "Renders its content in italics.",
+        args: Args::None,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "u",
+        kind: BlockKind::Inline,
+        render: Render::Underline,
+        summary: "Underlined text",
+        doc: "Renders its content underlined.",
+        args: Args::None,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "link",
+        kind: BlockKind::Inline,
+        render: Render::Link,
+        summary: "Hyperlink",
+        doc: "Links its content to the URL given in parentheses, as in \
+              `@link(url) {text}`.",
+        args: Args::Required,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+    BlockSchema {
+        name: "ref",
+        kind: BlockKind::Inline,
+        render: Render::Ref,
+        summary: "Cross-reference",
+        doc: "Links to the block whose `#id` is given in parentheses, as in \
+              `@ref(intro) {text}`. Sections also answer to a slug of their \
+              title, and `@ref(id) {}` shows the title of the target.",
+        args: Args::Required,
+        params: &[],
+        exclusive: &[],
+        attrs: &[],
+        children: Children::Any,
+    },
+];
+
+/// Looks up a built-in block or inline marker by name.
+pub fn lookup_block(name: &str) -> Option<&'static BlockSchema> {
+    BUILTIN_BLOCKS.iter().find(|schema| schema.name == name)
+}
+
+/// Returns whether `name` is a built-in block whose content is verbatim.
+pub(crate) fn is_raw_block(name: &str) -> bool {
+    lookup_block(name).is_some_and(|schema| schema.children == Children::Raw)
+}
+
+/// Checks every block and inline marker in `document` against `schemas`,
+/// writing messages in `locale`.
+pub fn validate_document(
+    document: &Document,
+    schemas: &[BlockSchema],
+    locale: Locale,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    validate_nodes(&document.nodes, schemas, locale, &mut diagnostics);
+    diagnostics
+}
+
+fn validate_nodes(
+    nodes: &[Node],
+    schemas: &[BlockSchema],
+    locale: Locale,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for node in nodes {
+        match node {
+            Node::Block(block) => {
+                validate_block(block, schemas, locale, diagnostics);
+                if find(schemas, &block.name).is_none_or(|schema| schema.children != Children::Raw)
+                {
+                    validate_nodes(&block.nodes, schemas, locale, diagnostics);
+                }
+            }
+            Node::Text(text) => {
+                for line in &text.lines {
+                    validate_inlines(&line.inlines, schemas, locale, diagnostics);
+                }
+            }
+            Node::Region(region) => validate_nodes(&region.nodes, schemas, locale, diagnostics),
+        }
+    }
+}
+
+/// Checks one block (but not its children) against `schemas`.
+pub(crate) fn validate_block(
+    block: &Block,
+    schemas: &[BlockSchema],
+    locale: Locale,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let span = block.span;
+    let name = block.name.as_str();
+    let mut push = |code, key, args: &[(&str, &str)], span, severity| {
+        let message = message(locale, code, key, args);
+        diagnostics.push(Diagnostic::new(code, severity, span, message));
+    };
+    let Some(schema) = find(schemas, name).filter(|schema| schema.kind == BlockKind::Block) else {
+        push(
+            DiagnosticCode::UnknownBlock,
+            "",
+            &[("name", name)],
+            span,
+            Severity::Warning,
+        );
+        return;
+    };
+
+    match schema.args {
+        Args::None if !block.args.is_empty() => {
+            push(
+                DiagnosticCode::UnexpectedArgument,
+                "",
+                &[("name", name)],
+                span,
+                Severity::Warning,
+            );
+        }
+        Args::Required if block.args.is_empty() => {
+            push(
+                DiagnosticCode::MissingArgument,
+                "block",
+                &[("name", name)],
+                span,
+                Severity::Warning,
+            );
+        }
+        Args::Flags => {
+            for arg in &block.args {
+                let known = schema
+                    .params
+                    .iter()
+                    .any(|param| param.name == arg && param.value == ParamValue::Flag);
+                if !known {
+                    push(
+                        DiagnosticCode::UnknownParam,
+                        "",
+                        &[("name", name), ("param", arg)],
+                        span,
+                        Severity::Warning,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+
+    for param in &block.params {
+        let key = param.key.as_str();
+        let Some(param_schema) = schema.params.iter().find(|known| known.name == key) else {
+            push(
+                DiagnosticCode::UnknownParam,
+                "",
+                &[("name", name), ("param", key)],
+                span,
+                Severity::Warning,
+            );
+            continue;
+        };
+        let value = param.value.as_str();
+        match param_schema.value {
+            ParamValue::Flag if !value.is_empty() => {
+                push(
+                    DiagnosticCode::InvalidParamValue,
+                    "flag",
+                    &[("param", key)],
+                    span,
+                    Severity::Warning,
+                );
+            }
+            ParamValue::Text(_) if value.is_empty() => {
+                push(
+                    DiagnosticCode::InvalidParamValue,
+                    "empty",
+                    &[("param", key)],
+                    span,
+                    Severity::Warning,
+                );
+            }
+            ParamValue::Enum(values) if !values.contains(&value) => {
+                let expected = values.join(", ");
+                push(
+                    DiagnosticCode::InvalidParamValue,
+                    "",
+                    &[("param", key), ("value", value), ("expected", &expected)],
+                    span,
+                    Severity::Error,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    for group in schema.exclusive {
+        let given: Vec<&str> = group
+            .iter()
+            .copied()
+            .filter(|flag| {
+                block.params.iter().any(|param| param.key == *flag)
+                    || block.args.iter().any(|arg| arg == *flag)
+            })
+            .collect();
+        if let [first, rest @ ..] = given.as_slice()
+            && !rest.is_empty()
+        {
+            let second = rest.join("', '");
+            push(
+                DiagnosticCode::ConflictingParams,
+                "",
+                &[("name", name), ("first", first), ("second", &second)],
+                span,
+                Severity::Error,
+            );
+        }
+    }
+
+    for attr in schema.attrs.iter().filter(|attr| attr.required) {
+        if !block.attrs.iter().any(|given| given.key == attr.name) {
+            push(
+                DiagnosticCode::MissingAttribute,
+                "",
+                &[("name", name), ("attr", attr.name)],
+                span,
+                Severity::Error,
+            );
+        }
+    }
+
+    if let Children::Only(allowed) = schema.children {
+        for node in &block.nodes {
+            if let Node::Block(child) = node
+                && !allowed.contains(&child.name.as_str())
+            {
+                push(
+                    DiagnosticCode::DisallowedChild,
+                    "",
+                    &[("name", name), ("child", &child.name)],
+                    child.span,
+                    Severity::Warning,
+                );
+            }
+        }
+    }
+}
+
+/// Checks the inline markers of one line against `schemas`.
+pub(crate) fn validate_inlines(
+    inlines: &[Inline],
+    schemas: &[BlockSchema],
+    locale: Locale,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for inline in inlines {
+        let Inline::Marker(marker) = inline else {
+            continue;
+        };
+        let name = marker.name.as_str();
+        let code = match find(schemas, name).filter(|schema| schema.kind == BlockKind::Inline) {
+            None => Some(DiagnosticCode::UnknownInline),
+            Some(schema) => match (schema.args, &marker.arg) {
+                (Args::Required, None) => Some(DiagnosticCode::MissingArgument),
+                (Args::None, Some(_)) => Some(DiagnosticCode::UnexpectedArgument),
+                _ => None,
+            },
+        };
+        if let Some(code) = code {
+            let message = message(locale, code, "", &[("name", name)]);
+            diagnostics.push(Diagnostic::new(
+                code,
+                Severity::Warning,
+                marker.span,
+                message,
+            ));
+        }
+        validate_inlines(&marker.nodes, schemas, locale, diagnostics);
+    }
+}
+
+fn find<'s>(schemas: &'s [BlockSchema], name: &str) -> Option<&'s BlockSchema> {
+    schemas.iter().find(|schema| schema.name == name)
+}
@@ -0,0 +1,363 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use data_classes::derive::*;
+use smol_str::SmolStr;
+
+use crate::ast::{Position, Span};
+use crate::parser::position_for_line_offset;
+
+/// Kinds of tokens and nodes in the concrete syntax tree.
+#[data(copy)]
+pub enum SyntaxKind {
+    /// Spaces and tabs (and other non-newline whitespace).
+    Whitespace,
+    /// A single `\n`.
+    Newline,
+    /// Raw, still-escaped text content.
+    Text,
+    /// A whole comment line starting at its `!`.
+    Comment,
+    /// A `$` region marker.
+    Dollar,
+    /// The `@` starting a block header.
+    At,
+    /// The block name after `@`.
+    Name,
+    /// A positional header argument.
+    Arg,
+    /// A bracketed parameter list including `[` and `]`.
+    Params,
+    /// The run of `+` before an opening `{`.
+    Plus,
+    /// The opening `{` of a block.
+    LBrace,
+    /// A closing delimiter such as `}` or `}++`.
+    Close,
+    /// The `#` starting an attribute line.
+    Hash,
+    /// An attribute key.
+    AttrKey,
+    /// The `:` separating an attribute key and value.
+    Colon,
+    /// An attribute value.
+    AttrValue,
+    /// Header text the parser does not understand.
+    Unknown,
+
+    /// Root node covering the whole input.
+    Document,
+    /// A block from its header through its closing delimiter.
+    Block,
+    /// A block header from `@` through `{`.
+    Header,
+    /// A `#key: value` line.
+    AttrLine,
+    /// A line (or line segment) of text.
+    TextLine,
+    /// A `!` comment line.
+    CommentLine,
+    /// A `$ ... $` region including both markers.
+    DollarRegion,
+    /// Input that could not be parsed, kept for losslessness.
+    Error,
+}
+
+/// Immutable, position-independent tree node; children are shared by `Arc`.
+#[data]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    len: usize,
+    children: Vec<GreenElement>,
+}
+
+/// A child of a [`GreenNode`].
+#[data]
+pub enum GreenElement {
+    Node(Arc<GreenNode>),
+    Token(GreenToken),
+}
+
+/// A leaf carrying the exact source text.
+#[data]
+pub struct GreenToken {
+    kind: SyntaxKind,
+    text: SmolStr,
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::len).sum();
+        Self {
+            kind,
+            len,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    /// Length of the covered source text in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+
+    fn write_text(&self, out: &mut String) {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => node.write_text(out),
+                GreenElement::Token(token) => out.push_str(&token.text),
+            }
+        }
+    }
+}
+
+impl GreenElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            GreenElement::Node(node) => node.kind,
+            GreenElement::Token(token) => token.kind,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len,
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: &str) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// A green node positioned at a byte offset in the source.
+#[data]
+pub struct SyntaxNode {
+    green: Arc<GreenNode>,
+    offset: usize,
+}
+
+/// A green token positioned at a byte offset in the source.
+#[data]
+pub struct SyntaxToken {
+    kind: SyntaxKind,
+    text: SmolStr,
+    offset: usize,
+}
+
+/// A positioned child of a [`SyntaxNode`].
+#[data]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Arc<GreenNode>) -> Self {
+        Self { green, offset: 0 }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind
+    }
+
+    pub fn green(&self) -> &Arc<GreenNode> {
+        &self.green
+    }
+
+    /// Byte range covered by this node.
+    pub fn text_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.len
+    }
+
+    /// Reproduces the exact source text covered by this node.
+    pub fn text(&self) -> String {
+        let mut out = String::with_capacity(self.green.len);
+        self.green.write_text(&mut out);
+        out
+    }
+
+    pub fn children(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.offset;
+        self.green.children.iter().map(move |child| {
+            let start = offset;
+            offset += child.len();
+            match child {
+                GreenElement::Node(node) => SyntaxElement::Node(SyntaxNode {
+                    green: node.clone(),
+                    offset: start,
+                }),
+                GreenElement::Token(token) => SyntaxElement::Token(SyntaxToken {
+                    kind: token.kind,
+                    text: token.text.clone(),
+                    offset: start,
+                }),
+            }
+        })
+    }
+
+    pub fn child_nodes(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    pub fn child_tokens(&self) -> impl Iterator<Item = SyntaxToken> + '_ {
+        self.children().filter_map(|child| match child {
+            SyntaxElement::Token(token) => Some(token),
+            SyntaxElement::Node(_) => None,
+        })
+    }
+
+    pub fn first_child_node(&self, kind: SyntaxKind) -> Option<SyntaxNode> {
+        self.child_nodes().find(|node| node.kind() == kind)
+    }
+
+    pub fn first_child_token(&self, kind: SyntaxKind) -> Option<SyntaxToken> {
+        self.child_tokens().find(|token| token.kind == kind)
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Byte range covered by this token.
+    pub fn text_range(&self) -> Range<usize> {
+        self.offset..self.offset + self.text.len()
+    }
+}
+
+/// Builds a green tree from nested `start_node`/`token`/`finish_node` calls.
+pub(crate) struct TreeBuilder {
+    stack: Vec<(SyntaxKind, Vec<GreenElement>)>,
+    root: Option<Arc<GreenNode>>,
+}
+
+impl TreeBuilder {
+    pub(crate) fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            root: None,
+        }
+    }
+
+    pub(crate) fn start_node(&mut self, kind: SyntaxKind) {
+        self.stack.push((kind, Vec::new()));
+    }
+
+    pub(crate) fn token(&mut self, kind: SyntaxKind, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let (_, children) = self.stack.last_mut().expect("token outside of node");
+        children.push(GreenElement::Token(GreenToken::new(kind, text)));
+    }
+
+    /// Appends an existing green element to the current node.
+    pub(crate) fn push(&mut self, element: GreenElement) {
+        let (_, children) = self.stack.last_mut().expect("element outside of node");
+        children.push(element);
+    }
+
+    /// Number of elements in the current node, for [`TreeBuilder::rollback`].
+    pub(crate) fn checkpoint(&self) -> usize {
+        self.stack.last().map_or(0, |(_, children)| children.len())
+    }
+
+    /// Drops the elements added to the current node since `checkpoint`.
+    pub(crate) fn rollback(&mut self, checkpoint: usize) {
+        if let Some((_, children)) = self.stack.last_mut() {
+            children.truncate(checkpoint);
+        }
+    }
+
+    pub(crate) fn finish_node(&mut self) {
+        let (kind, children) = self.stack.pop().expect("unbalanced finish_node");
+        let node = Arc::new(GreenNode::new(kind, children));
+        match self.stack.last_mut() {
+            Some((_, parent)) => parent.push(GreenElement::Node(node)),
+            None => self.root = Some(node),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Arc<GreenNode> {
+        assert!(self.stack.is_empty(), "unfinished syntax nodes");
+        self.root.expect("no root node")
+    }
+}
+
+/// Maps byte offsets in a source text to [`Position`]s.
+pub(crate) struct LineIndex<'a> {
+    input: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(idx, _)| idx + 1))
+            .collect();
+        Self { input, line_starts }
+    }
+
+    pub(crate) fn position(&self, offset: usize) -> Position {
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let start = self.line_starts[line];
+        let prefix = &self.input[start..offset];
+        position_for_line_offset(line, prefix, prefix.len())
+    }
+
+    /// Byte range of the line containing `offset` and its line break, if any.
+    pub(crate) fn line_range(&self, offset: usize) -> Range<usize> {
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let start = self.line_starts[line];
+        match self.line_starts.get(line + 1) {
+            Some(next) => start..*next,
+            None => start..self.input.len(),
+        }
+    }
+
+    pub(crate) fn span(&self, range: Range<usize>) -> Span {
+        Span::new(self.position(range.start), self.position(range.end))
+    }
+
+    pub(crate) fn is_line_start(&self, offset: usize) -> bool {
+        self.line_starts.binary_search(&offset).is_ok()
+    }
+}
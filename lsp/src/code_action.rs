@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use lmm::{Fix, ParseResult};
+use lsp::lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Diagnostic, Range, Url, WorkspaceEdit,
+};
+
+use crate::{edit_to_text_edit, lsp_diagnostic, span_to_range};
+
+/// Offers the fixes of every diagnostic that overlaps `range` as quick fixes.
+pub(crate) fn quick_fixes(
+    uri: &Url,
+    parsed: &ParseResult,
+    range: Range,
+) -> Vec<CodeActionOrCommand> {
+    let mut actions = Vec::new();
+    for diag in &parsed.diagnostics {
+        let diag_range = span_to_range(diag.span);
+        if diag_range.start > range.end || range.start > diag_range.end {
+            continue;
+        }
+        let lsp_diag = lsp_diagnostic(uri, diag);
+        for (index, fix) in diag.fixes.iter().enumerate() {
+            let action = quick_fix(uri, fix, lsp_diag.clone(), index == 0);
+            actions.push(CodeActionOrCommand::CodeAction(action));
+        }
+    }
+    actions
+}
+
+fn quick_fix(uri: &Url, fix: &Fix, diagnostic: Diagnostic, preferred: bool) -> CodeAction {
+    let edits = fix.edits.iter().cloned().map(edit_to_text_edit).collect();
+    CodeAction {
+        title: fix.title.to_string(),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic]),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..Default::default()
+        }),
+        is_preferred: Some(preferred),
+        ..Default::default()
+    }
+}
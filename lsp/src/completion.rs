@@ -0,0 +1,295 @@
+use lmm::{
+    Args, AttrSchema, BLOCK_ATTRS, BUILTIN_BLOCKS, Block, BlockKind, BlockSchema, DOCUMENT_ATTRS,
+    DiagnosticCode, Locale, ParamValue, ParseResult, Render, anchors, lookup_block, message,
+};
+use lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, InsertTextFormat, Position, Range,
+    TextEdit,
+};
+
+use crate::context::{enclosing_blocks, in_raw_content};
+use crate::offset_at;
+
+/// Returns the completions that fit the text before `position`, describing
+/// them in `locale` where the message catalog covers them.
+pub(crate) fn completions(
+    text: &str,
+    parsed: &ParseResult,
+    position: Position,
+    locale: Locale,
+) -> Vec<CompletionItem> {
+    let lines: Vec<&str> = text.lines().collect();
+    let line = lines.get(position.line as usize).copied().unwrap_or("");
+    let prefix = &line[..offset_at(line, Position::new(0, position.character))];
+    let trimmed = prefix.trim_start();
+    let chain = enclosing_blocks(&parsed.document.nodes, position);
+    let block = chain.last().copied();
+    let in_raw = in_raw_content(&chain, position);
+
+    let mut items = Vec::new();
+    if let Some(block) = block
+        && (trimmed.is_empty() || is_closer_prefix(trimmed))
+    {
+        items.push(closer_completion(block, prefix, position, locale));
+    }
+    if in_raw {
+        return items;
+    }
+
+    if let Some(partial) = ref_arg_prefix(prefix) {
+        let range = word_range(prefix, partial, position);
+        items.extend(id_completions(parsed, range));
+        return items;
+    }
+
+    if let Some(header) = trimmed.strip_prefix('@')
+        && !header.starts_with('@')
+    {
+        if is_name(header) {
+            let range = word_range(prefix, header, position);
+            items.extend(snippets(BlockKind::Block, range));
+            items.extend(snippets(BlockKind::Inline, range));
+        } else if let Some(params) = open_params(header) {
+            items.extend(param_completions(header, params, prefix, position));
+        }
+        return items;
+    }
+
+    if let Some(key) = trimmed.strip_prefix('#')
+        && is_name(key)
+        && in_attribute_zone(&lines, block, position.line as usize)
+    {
+        let range = word_range(prefix, key, position);
+        let known: Vec<&AttrSchema> = match block {
+            Some(block) => {
+                let specific = lookup_block(&block.name).map_or(&[][..], |schema| schema.attrs);
+                BLOCK_ATTRS.iter().chain(specific).collect()
+            }
+            None => DOCUMENT_ATTRS.iter().collect(),
+        };
+        items.extend(known.into_iter().map(|attr| CompletionItem {
+            label: attr.name.to_string(),
+            kind: Some(CompletionItemKind::PROPERTY),
+            detail: Some(attr.doc.to_string()),
+            text_edit: Some(edit(range, format!("{}: ", attr.name))),
+            ..Default::default()
+        }));
+        return items;
+    }
+
+    if let Some(name) = inline_marker_prefix(prefix) {
+        let range = word_range(prefix, name, position);
+        items.extend(snippets(BlockKind::Inline, range));
+    }
+    items
+}
+
+/// Offers a snippet for every built-in schema of `kind`.
+fn snippets(kind: BlockKind, range: Range) -> impl Iterator<Item = CompletionItem> {
+    let item_kind = match kind {
+        BlockKind::Block => CompletionItemKind::KEYWORD,
+        BlockKind::Inline => CompletionItemKind::TEXT,
+    };
+    BUILTIN_BLOCKS
+        .iter()
+        .filter(move |schema| schema.kind == kind)
+        .map(move |schema| CompletionItem {
+            label: schema.name.to_string(),
+            kind: Some(item_kind),
+            detail: Some(schema.summary.to_string()),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            text_edit: Some(edit(range, snippet(schema))),
+            ..Default::default()
+        })
+}
+
+/// Builds the snippet body for `schema`, prompting for what its header takes.
+fn snippet(schema: &BlockSchema) -> String {
+    let name = schema.name;
+    if schema.kind == BlockKind::Inline {
+        return match schema.args {
+            Args::Required => format!("{name}($1) {{$2}}"),
+            _ => format!("{name} {{$1}}"),
+        };
+    }
+    let text_param = schema
+        .params
+        .iter()
+        .find(|param| matches!(param.value, ParamValue::Text(_) | ParamValue::Enum(_)));
+    let header = if let Some(group) = schema.exclusive.first() {
+        format!("[${{1|{}|}}]", group.join(","))
+    } else if let Some(param) = text_param {
+        format!("[{}=$1]", param.name)
+    } else if schema.args == Args::Free {
+        " ${1:title}".to_string()
+    } else {
+        String::new()
+    };
+    format!("{name}{header} {{\n  $0\n}}")
+}
+
+fn param_completions(
+    header: &str,
+    params: &str,
+    prefix: &str,
+    position: Position,
+) -> Vec<CompletionItem> {
+    let name_len = header.bytes().take_while(|b| is_name_byte(*b)).count();
+    let Some(schema) = lookup_block(&header[..name_len]) else {
+        return Vec::new();
+    };
+    let current = params.rsplit(',').next().unwrap_or("").trim_start();
+    if let Some((key, value)) = current.split_once('=') {
+        let values = match schema.params.iter().find(|param| param.name == key.trim()) {
+            Some(param) => match param.value {
+                ParamValue::Text(values) | ParamValue::Enum(values) => values,
+                ParamValue::Flag => &[],
+            },
+            None => &[],
+        };
+        let range = word_range(prefix, value, position);
+        return values
+            .iter()
+            .map(|value| CompletionItem {
+                label: value.to_string(),
+                kind: Some(CompletionItemKind::ENUM_MEMBER),
+                text_edit: Some(edit(range, value.to_string())),
+                ..Default::default()
+            })
+            .collect();
+    }
+    let range = word_range(prefix, current, position);
+    let used: Vec<&str> = params
+        .split(',')
+        .map(|param| param.split('=').next().unwrap_or("").trim())
+        .collect();
+    schema
+        .params
+        .iter()
+        .filter(|param| !used[..used.len() - 1].contains(&param.name))
+        .map(|param| {
+            let text = match param.value {
+                ParamValue::Flag => param.name.to_string(),
+                _ => format!("{}=", param.name),
+            };
+            CompletionItem {
+                label: text.clone(),
+                kind: Some(CompletionItemKind::PROPERTY),
+                detail: Some(param.doc.to_string()),
+                text_edit: Some(edit(range, text)),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Offers every anchor id of the document, once each.
+fn id_completions(parsed: &ParseResult, range: Range) -> Vec<CompletionItem> {
+    let mut items: Vec<CompletionItem> = Vec::new();
+    for anchor in anchors(&parsed.document) {
+        if items.iter().any(|item| item.label == anchor.id) {
+            continue;
+        }
+        items.push(CompletionItem {
+            label: anchor.id.to_string(),
+            kind: Some(CompletionItemKind::REFERENCE),
+            detail: anchor.title.map(|title| title.to_string()),
+            text_edit: Some(edit(range, anchor.id.to_string())),
+            ..Default::default()
+        });
+    }
+    items
+}
+
+/// Offers the delimiter that closes `block`, matching its `+` count.
+fn closer_completion(
+    block: &Block,
+    prefix: &str,
+    position: Position,
+    locale: Locale,
+) -> CompletionItem {
+    let closer = format!("}}{}", "+".repeat(block.plus_count));
+    let typed = prefix.trim_start();
+    let range = word_range(prefix, typed, position);
+    CompletionItem {
+        label: closer.clone(),
+        kind: Some(CompletionItemKind::OPERATOR),
+        detail: Some(message(
+            locale,
+            DiagnosticCode::UnclosedBlock,
+            "complete",
+            &[("name", &block.name)],
+        )),
+        text_edit: Some(edit(range, closer)),
+        ..Default::default()
+    }
+}
+
+/// Returns whether `line` sits where attributes are still allowed: directly
+/// after the enclosing header (or the top of the document), with only other
+/// attribute lines in between.
+fn in_attribute_zone(lines: &[&str], block: Option<&Block>, line: usize) -> bool {
+    let first = match block {
+        Some(block) if line <= block.span.end.line => return false,
+        Some(block) => block.span.end.line + 1,
+        None => 0,
+    };
+    let line = line.min(lines.len());
+    lines[first.min(line)..line].iter().all(|text| {
+        let text = text.trim_start();
+        text.starts_with('#') && !text.starts_with("##") && text.contains(':')
+    })
+}
+
+/// Returns the unclosed `[...]` contents of a header typed so far.
+fn open_params(header: &str) -> Option<&str> {
+    let open = header.find('[')?;
+    let params = &header[open + 1..];
+    (!params.contains(']')).then_some(params)
+}
+
+/// Returns the partial marker name if `prefix` ends in an unescaped `@name`.
+fn inline_marker_prefix(prefix: &str) -> Option<&str> {
+    let at = prefix.rfind('@')?;
+    let name = &prefix[at + 1..];
+    let run = prefix[..=at]
+        .bytes()
+        .rev()
+        .take_while(|b| *b == b'@')
+        .count();
+    (is_name(name) && run % 2 == 1).then_some(name)
+}
+
+/// Returns the partial id if `prefix` ends inside the argument of a
+/// cross-reference marker, as in `see @ref(int`.
+fn ref_arg_prefix(prefix: &str) -> Option<&str> {
+    let open = prefix.rfind('(')?;
+    let name = inline_marker_prefix(&prefix[..open])?;
+    let partial = &prefix[open + 1..];
+    let is_ref = lookup_block(name).is_some_and(|schema| schema.render == Render::Ref);
+    (is_ref && !partial.contains(')')).then_some(partial)
+}
+
+fn is_closer_prefix(text: &str) -> bool {
+    text.strip_prefix('}')
+        .is_some_and(|rest| rest.bytes().all(|b| b == b'+'))
+}
+
+fn is_name(text: &str) -> bool {
+    text.bytes().all(is_name_byte)
+}
+
+fn is_name_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+/// Range covering `word`, which ends `prefix`, on the cursor's line.
+fn word_range(prefix: &str, word: &str, position: Position) -> Range {
+    let start = prefix.len() - word.len();
+    let start16 = prefix[..start].encode_utf16().count() as u32;
+    Range::new(Position::new(position.line, start16), position)
+}
+
+fn edit(range: Range, new_text: String) -> CompletionTextEdit {
+    CompletionTextEdit::Edit(TextEdit::new(range, new_text))
+}
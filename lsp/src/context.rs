@@ -0,0 +1,56 @@
+use lmm::{Block, Children, Node, Span, lookup_block};
+use lsp::lsp_types::Position;
+
+/// Returns the blocks containing `position`, outermost first.
+pub(crate) fn enclosing_blocks(nodes: &[Node], position: Position) -> Vec<&Block> {
+    let mut chain = Vec::new();
+    let mut nodes = nodes;
+    'outer: loop {
+        for node in nodes {
+            if let Node::Block(block) = node
+                && block_contains(block, position)
+            {
+                chain.push(block);
+                nodes = &block.nodes;
+                continue 'outer;
+            }
+        }
+        return chain;
+    }
+}
+
+fn block_contains(block: &Block, position: Position) -> bool {
+    let at = cursor(position);
+    let start = block.span.start;
+    (start.line, start.col16) <= at
+        && block
+            .close_span
+            .is_none_or(|close| at <= (close.end.line, close.end.col16))
+}
+
+/// Returns whether `position` is in the verbatim body of the innermost block
+/// of `chain`, such as a code block's content.
+pub(crate) fn in_raw_content(chain: &[&Block], position: Position) -> bool {
+    chain.last().is_some_and(|block| {
+        lookup_block(&block.name).is_some_and(|schema| schema.children == Children::Raw)
+            && block_body_contains(block, position)
+    })
+}
+
+/// Returns whether the cursor is below the header of `block`, inside its body.
+fn block_body_contains(block: &Block, position: Position) -> bool {
+    let line = position.line as usize;
+    line > block.span.end.line
+        && block
+            .close_span
+            .is_none_or(|close| line <= close.start.line)
+}
+
+pub(crate) fn contains(span: Span, position: Position) -> bool {
+    let at = cursor(position);
+    (span.start.line, span.start.col16) <= at && at <= (span.end.line, span.end.col16)
+}
+
+fn cursor(position: Position) -> (usize, usize) {
+    (position.line as usize, position.character as usize)
+}
@@ -0,0 +1,66 @@
+use lmm::{FormatOptions, ParseResult, format_parsed_edits, format_parsed_range_edits};
+use lsp::lsp_types::{FormattingOptions, Position, Range, TextEdit};
+
+use crate::edit_to_text_edit;
+
+/// Edits that format the whole document.
+pub(crate) fn document_edits(
+    text: &str,
+    parsed: &ParseResult,
+    options: &FormattingOptions,
+) -> Vec<TextEdit> {
+    let edits = format_parsed_edits(text, parsed, format_options(options));
+    edits.into_iter().map(edit_to_text_edit).collect()
+}
+
+/// Edits that format the lines `range` touches.
+pub(crate) fn range_edits(
+    text: &str,
+    parsed: &ParseResult,
+    range: Range,
+    options: &FormattingOptions,
+) -> Vec<TextEdit> {
+    let start = range.start.line as usize;
+    let mut end = range.end.line as usize;
+    // A selection ending at the start of a line leaves that line out.
+    if end > start && range.end.character == 0 {
+        end -= 1;
+    }
+    lines_edits(text, parsed, start, end, options)
+}
+
+/// Edits that format the line just finished by typing `ch` at `position`.
+pub(crate) fn on_type_edits(
+    text: &str,
+    parsed: &ParseResult,
+    ch: &str,
+    position: Position,
+    options: &FormattingOptions,
+) -> Vec<TextEdit> {
+    // After a newline the cursor sits on the fresh line; format the one just finished.
+    let line = match ch {
+        "\n" if position.line == 0 => return Vec::new(),
+        "\n" => position.line as usize - 1,
+        _ => position.line as usize,
+    };
+    lines_edits(text, parsed, line, line, options)
+}
+
+fn lines_edits(
+    text: &str,
+    parsed: &ParseResult,
+    start: usize,
+    end: usize,
+    options: &FormattingOptions,
+) -> Vec<TextEdit> {
+    let edits = format_parsed_range_edits(text, parsed, start, end, format_options(options));
+    edits.into_iter().map(edit_to_text_edit).collect()
+}
+
+fn format_options(options: &FormattingOptions) -> FormatOptions {
+    FormatOptions {
+        use_tabs: !options.insert_spaces,
+        indent_width: options.tab_size as usize,
+        ..FormatOptions::default()
+    }
+}
@@ -0,0 +1,115 @@
+use lmm::{
+    Block, HtmlOptions, ParseResult, Render, heading_level, lookup_block, scan_escapes,
+    section_depth,
+};
+use lsp::lsp_types::{Hover, HoverContents, MarkupContent, MarkupKind, Position, Range};
+
+use crate::context::{contains, enclosing_blocks, in_raw_content};
+use crate::{offset_at, span_to_range};
+
+/// Describes whatever sits under `position`: a block header, an escape, or
+/// otherwise the innermost block enclosing the cursor.
+pub(crate) fn hover(text: &str, parsed: &ParseResult, position: Position) -> Option<Hover> {
+    let chain = enclosing_blocks(&parsed.document.nodes, position);
+    if let Some((block, ancestors)) = chain.split_last()
+        && contains(block.span, position)
+    {
+        let summary = block_summary(block, ancestors);
+        return Some(markdown_hover(summary, Some(span_to_range(block.span))));
+    }
+    // Escapes are not interpreted in verbatim content.
+    if !in_raw_content(&chain, position)
+        && let Some(hover) = escape_hover(text, position)
+    {
+        return Some(hover);
+    }
+    let (block, ancestors) = chain.split_last()?;
+    Some(markdown_hover(block_summary(block, ancestors), None))
+}
+
+fn block_summary(block: &Block, ancestors: &[&Block]) -> String {
+    let mut out = format!("**@{}**", block.name);
+    match lookup_block(&block.name) {
+        Some(schema) => {
+            out.push_str(" — built-in block\n\n");
+            out.push_str(schema.doc);
+        }
+        None => out.push_str(" — custom block, rendered as a generic container"),
+    }
+    if lookup_block(&block.name).is_some_and(|schema| schema.render == Render::Section) {
+        let depth = section_depth(ancestors);
+        let html = heading_level(depth, HtmlOptions::default().heading_offset);
+        let markdown = heading_level(depth, 0);
+        out.push_str(&format!(
+            "\n\nRenders as a level {markdown} heading (`<h{html}>`, `{}`).",
+            "#".repeat(markdown)
+        ));
+    }
+    if !block.args.is_empty() {
+        out.push_str("\n\nArguments:");
+        for arg in &block.args {
+            out.push(' ');
+            out.push_str(&inline_code(arg));
+        }
+    }
+    if !block.params.is_empty() {
+        out.push_str("\n\nParams:");
+        for param in &block.params {
+            out.push_str("\n- ");
+            out.push_str(&inline_code(&param.key));
+            if !param.value.is_empty() {
+                out.push_str(" = ");
+                out.push_str(&inline_code(&param.value));
+            }
+        }
+    }
+    if !block.attrs.is_empty() {
+        out.push_str("\n\nAttributes:");
+        for attr in &block.attrs {
+            out.push_str("\n- ");
+            out.push_str(&inline_code(&format!("#{}", attr.key)));
+            out.push_str(": ");
+            out.push_str(&inline_code(&attr.value));
+        }
+    }
+    out
+}
+
+fn escape_hover(text: &str, position: Position) -> Option<Hover> {
+    let line = text.lines().nth(position.line as usize)?;
+    let offset = offset_at(line, Position::new(0, position.character));
+    let escape = scan_escapes(line, true)
+        .into_iter()
+        .find(|escape| escape.start <= offset && offset < escape.end)?;
+    let raw = &line[escape.start..escape.end];
+    let value = format!(
+        "Escape {} renders as {}",
+        inline_code(raw),
+        inline_code(&escape.value)
+    );
+    let col16 = |offset: usize| line[..offset].encode_utf16().count() as u32;
+    let range = Range::new(
+        Position::new(position.line, col16(escape.start)),
+        Position::new(position.line, col16(escape.end)),
+    );
+    Some(markdown_hover(value, Some(range)))
+}
+
+/// Wraps `value` in a Markdown code span that survives embedded backticks.
+fn inline_code(value: &str) -> String {
+    if value.contains('`') {
+        format!("`` {value} ``")
+    } else {
+        format!("`{value}`")
+    }
+}
+
+fn markdown_hover(value: String, range: Option<Range>) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range,
+    }
+}
@@ -1,32 +1,84 @@
-use lmm::{Node, Severity, Span, parse_document};
+use lmm::{
+    Edit, Locale, Node, OutlineEntry, ParseOptions, ParseResult, Severity, Span, TextChange,
+    outline, parse_document_with_options, reparse_document_with_options,
+};
 use lsp::jsonrpc::Result;
 use lsp::lsp_types::*;
 use lsp::{Client, LanguageServer, LspService, Server};
 use std::collections::HashMap;
+use std::sync::OnceLock;
 use tokio::sync::RwLock;
 
 extern crate tower_lsp as lsp;
 
+mod code_action;
+mod completion;
+mod context;
+mod formatting;
+mod hover;
+mod reference;
+
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    documents: RwLock<HashMap<Url, String>>,
+    documents: RwLock<HashMap<Url, DocumentState>>,
+    /// Parse options chosen at initialization, such as the message locale.
+    options: OnceLock<ParseOptions>,
+}
+
+#[derive(Debug)]
+struct DocumentState {
+    version: i32,
+    text: String,
+    parsed: ParseResult,
+}
+
+impl DocumentState {
+    /// Whether a change stamped `version` is newer than this state; older or
+    /// repeated versions arrive out of order and must not be applied.
+    fn accepts(&self, version: i32) -> bool {
+        version > self.version
+    }
 }
 
 #[lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let locale = params.locale.as_deref().and_then(Locale::from_tag);
+        let _ = self.options.set(ParseOptions {
+            locale: locale.unwrap_or(Locale::En),
+            ..ParseOptions::default()
+        });
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                        ..Default::default()
+                    },
+                )),
                 completion_provider: Some(CompletionOptions {
-                    trigger_characters: Some(vec!["@".to_string(), "#".to_string()]),
+                    trigger_characters: Some(
+                        ["@", "#", "[", ",", "=", "}", "("]
+                            .map(String::from)
+                            .to_vec(),
+                    ),
                     ..Default::default()
                 }),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "}".to_string(),
+                    more_trigger_character: Some(vec!["\n".to_string()]),
+                }),
                 ..Default::default()
             },
             ..Default::default()
@@ -45,123 +97,202 @@ impl LanguageServer for Backend {
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         let uri = params.text_document.uri;
+        let version = params.text_document.version;
         let text = params.text_document.text;
-        self.store_document(uri.clone(), text.clone()).await;
-        self.on_change(uri, text).await;
+        let parsed = parse_document_with_options(&text, self.options());
+        let diagnostics = parsed.diagnostics.clone();
+        self.store_document(
+            uri.clone(),
+            DocumentState {
+                version,
+                text,
+                parsed,
+            },
+        )
+        .await;
+        self.on_change(uri, version, diagnostics).await;
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        if let Some(change) = params.content_changes.into_iter().next() {
-            let uri = params.text_document.uri;
-            let text = change.text;
-            self.store_document(uri.clone(), text.clone()).await;
-            self.on_change(uri, text).await;
-        }
+        let uri = params.text_document.uri;
+        let version = params.text_document.version;
+        let diagnostics = {
+            let mut docs = self.documents.write().await;
+            let Some(state) = docs.get(&uri) else {
+                return;
+            };
+            if !state.accepts(version) {
+                let message = format!(
+                    "Ignoring stale change to {uri} (version {version}, have {})",
+                    state.version
+                );
+                drop(docs);
+                self.client.log_message(MessageType::WARNING, message).await;
+                return;
+            }
+            let state = docs.remove(&uri).expect("document checked above");
+            let mut state = apply_changes(state, params.content_changes, self.options());
+            state.version = version;
+            let diagnostics = state.parsed.diagnostics.clone();
+            docs.insert(uri.clone(), state);
+            diagnostics
+        };
+        self.on_change(uri, version, diagnostics).await;
     }
 
-    async fn hover(&self, _params: HoverParams) -> Result<Option<Hover>> {
-        Ok(Some(Hover {
-            contents: HoverContents::Scalar(MarkedString::String(
-                "LumosMark element detected".to_string(),
-            )),
-            range: None,
-        }))
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let mut docs = self.documents.write().await;
+        docs.remove(&params.text_document.uri);
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params.position;
+        let hover = self
+            .with_document(
+                &params.text_document_position_params.text_document.uri,
+                |state| hover::hover(&state.text, &state.parsed, position),
+            )
+            .await;
+        Ok(hover.flatten())
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let actions = self
+            .with_document(&uri, |state| {
+                code_action::quick_fixes(&uri, &state.parsed, params.range)
+            })
+            .await;
+        Ok(actions)
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        self.get_completions(params).await
+        let position = params.text_document_position.position;
+        let locale = self.options().locale;
+        let items = self
+            .with_document(&params.text_document_position.text_document.uri, |state| {
+                completion::completions(&state.text, &state.parsed, position, locale)
+            })
+            .await;
+        Ok(items.map(CompletionResponse::Array))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let range = self
+            .with_document(&uri, |state| reference::definition(&state.parsed, position))
+            .await;
+        Ok(range
+            .flatten()
+            .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri, range))))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let include_declaration = params.context.include_declaration;
+        let ranges = self
+            .with_document(&uri, |state| {
+                reference::find_references(&state.parsed, position, include_declaration)
+            })
+            .await;
+        Ok(ranges.map(|ranges| {
+            ranges
+                .into_iter()
+                .map(|range| Location::new(uri.clone(), range))
+                .collect()
+        }))
     }
 
     async fn document_symbol(
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
-        let uri = params.text_document.uri;
-        let Some(text) = self.get_document(&uri).await else {
-            return Ok(None);
-        };
-        let result = parse_document(&text);
-        let symbols = collect_part_symbols(&result.document.nodes);
-        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+        let symbols = self
+            .with_document(&params.text_document.uri, |state| {
+                part_symbols(&outline(&state.parsed.document))
+            })
+            .await;
+        Ok(symbols.map(DocumentSymbolResponse::Nested))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let ranges = self
+            .with_document(&params.text_document.uri, |state| {
+                let mut ranges = Vec::new();
+                collect_folding_ranges(&state.parsed.document.nodes, &mut ranges);
+                ranges
+            })
+            .await;
+        Ok(ranges)
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let edits = self
+            .with_document(&params.text_document.uri, |state| {
+                formatting::document_edits(&state.text, &state.parsed, &params.options)
+            })
+            .await;
+        Ok(edits)
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let edits = self
+            .with_document(&params.text_document.uri, |state| {
+                formatting::range_edits(&state.text, &state.parsed, params.range, &params.options)
+            })
+            .await;
+        Ok(edits)
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let position = params.text_document_position.position;
+        let edits = self
+            .with_document(&params.text_document_position.text_document.uri, |state| {
+                let (text, parsed) = (&state.text, &state.parsed);
+                formatting::on_type_edits(text, parsed, &params.ch, position, &params.options)
+            })
+            .await;
+        Ok(edits.filter(|edits| !edits.is_empty()))
     }
 }
 
 impl Backend {
-    async fn store_document(&self, uri: Url, text: String) {
+    fn options(&self) -> ParseOptions {
+        self.options.get().copied().unwrap_or_default()
+    }
+
+    async fn store_document(&self, uri: Url, state: DocumentState) {
         let mut docs = self.documents.write().await;
-        docs.insert(uri, text);
+        docs.insert(uri, state);
     }
 
-    async fn get_document(&self, uri: &Url) -> Option<String> {
+    /// Runs `f` on the cached state of `uri`, if the document is open.
+    async fn with_document<R>(&self, uri: &Url, f: impl FnOnce(&DocumentState) -> R) -> Option<R> {
         let docs = self.documents.read().await;
-        docs.get(uri).cloned()
+        docs.get(uri).map(f)
     }
 
-    async fn on_change(&self, uri: Url, text: String) {
-        let mut diagnostics = Vec::new();
-
-        let result = parse_document(&text);
-        for diag in result.diagnostics {
-            let start = Position::new(diag.span.start.line as u32, diag.span.start.col16 as u32);
-            let end = Position::new(diag.span.end.line as u32, diag.span.end.col16 as u32);
-            let severity = match diag.severity {
-                Severity::Error => DiagnosticSeverity::ERROR,
-                Severity::Warning => DiagnosticSeverity::WARNING,
-            };
-            diagnostics.push(Diagnostic {
-                range: Range::new(start, end),
-                severity: Some(severity),
-                message: diag.message.to_string(),
-                source: Some("LumosMark".to_string()),
-                ..Default::default()
-            });
-        }
+    async fn on_change(&self, uri: Url, version: i32, lmm_diagnostics: Vec<lmm::Diagnostic>) {
+        let diagnostics = lmm_diagnostics
+            .iter()
+            .map(|diag| lsp_diagnostic(&uri, diag))
+            .collect();
 
         self.client
-            .publish_diagnostics(uri, diagnostics, None)
+            .publish_diagnostics(uri, diagnostics, Some(version))
             .await;
     }
-
-    async fn get_completions(
-        &self,
-        _params: CompletionParams,
-    ) -> Result<Option<CompletionResponse>> {
-        let completions = vec![
-            CompletionItem {
-                label: "part".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("定义章节".to_string()),
-                insert_text: Some("part { $1 }".to_string()),
-                insert_text_format: Some(InsertTextFormat::SNIPPET),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "list".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("列表块".to_string()),
-                insert_text: Some("list bullet {\n  $1\n}".to_string()),
-                insert_text_format: Some(InsertTextFormat::SNIPPET),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "code".to_string(),
-                kind: Some(CompletionItemKind::KEYWORD),
-                detail: Some("代码块".to_string()),
-                insert_text: Some("code[lang=$1] {\n  $2\n}".to_string()),
-                insert_text_format: Some(InsertTextFormat::SNIPPET),
-                ..Default::default()
-            },
-            CompletionItem {
-                label: "b".to_string(),
-                kind: Some(CompletionItemKind::TEXT),
-                detail: Some("粗体".to_string()),
-                insert_text: Some("b {$1}".to_string()),
-                insert_text_format: Some(InsertTextFormat::SNIPPET),
-                ..Default::default()
-            },
-        ];
-        Ok(Some(CompletionResponse::Array(completions)))
-    }
 }
 
 #[tokio::main]
@@ -172,43 +303,120 @@ async fn main() {
     let (service, socket) = LspService::new(|client| Backend {
         client,
         documents: RwLock::new(HashMap::new()),
+        options: OnceLock::new(),
     });
     Server::new(stdin, stdout, socket).serve(service).await;
 }
 
-fn collect_part_symbols(nodes: &[Node]) -> Vec<DocumentSymbol> {
-    let mut symbols = Vec::new();
-    for node in nodes {
-        if let Node::Block(block) = node {
-            let children = collect_part_symbols(&block.nodes);
-            if block.name == "part" {
-                let name = if block.args.is_empty() {
-                    "part".to_string()
-                } else {
-                    block
-                        .args
-                        .iter()
-                        .map(|arg| arg.to_string())
-                        .collect::<Vec<_>>()
-                        .join(" ")
-                };
-                #[allow(deprecated)]
-                symbols.push(DocumentSymbol {
-                    name,
-                    detail: None,
-                    kind: SymbolKind::NAMESPACE,
-                    tags: None,
-                    deprecated: None,
-                    range: span_to_range(block.span),
-                    selection_range: span_to_range(block.span),
-                    children: Some(children),
-                });
-            } else {
-                symbols.extend(children);
+fn part_symbols(entries: &[OutlineEntry]) -> Vec<DocumentSymbol> {
+    entries
+        .iter()
+        .map(|entry| {
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: entry.title.to_string(),
+                detail: None,
+                kind: SymbolKind::NAMESPACE,
+                tags: None,
+                deprecated: None,
+                range: span_to_range(entry.span),
+                selection_range: span_to_range(entry.header_span),
+                children: Some(part_symbols(&entry.children)),
             }
+        })
+        .collect()
+}
+
+fn collect_folding_ranges(nodes: &[Node], ranges: &mut Vec<FoldingRange>) {
+    for node in nodes {
+        let (span, close_span, children) = match node {
+            Node::Block(block) => (block.span, block.close_span, &block.nodes),
+            Node::Region(region) => (region.span, region.close_span, &region.nodes),
+            Node::Text(_) => continue,
+        };
+        // Keep the closing delimiter visible when folded.
+        let end = close_span.map(|span| span.start.line.saturating_sub(1));
+        if let Some(end) = end.filter(|end| *end > span.start.line) {
+            ranges.push(FoldingRange {
+                start_line: span.start.line as u32,
+                end_line: end as u32,
+                kind: Some(FoldingRangeKind::Region),
+                ..Default::default()
+            });
         }
+        collect_folding_ranges(children, ranges);
     }
-    symbols
+}
+
+/// Applies editor changes in order, reparsing only what each one touches.
+fn apply_changes(
+    mut state: DocumentState,
+    changes: Vec<TextDocumentContentChangeEvent>,
+    options: ParseOptions,
+) -> DocumentState {
+    for change in changes {
+        let Some(range) = change.range else {
+            state.parsed = parse_document_with_options(&change.text, options);
+            state.text = change.text;
+            continue;
+        };
+        let start = offset_at(&state.text, range.start);
+        let end = offset_at(&state.text, range.end).max(start);
+        state.text.replace_range(start..end, &change.text);
+        let change = TextChange::new(start, end, change.text.len());
+        state.parsed = reparse_document_with_options(state.parsed, &state.text, change, options);
+    }
+    state
+}
+
+/// Converts an LSP position (UTF-16 columns) to a byte offset in `text`.
+fn offset_at(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(offset) => line_start += offset + 1,
+            None => return text.len(),
+        }
+    }
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |offset| line_start + offset);
+    let mut col16 = 0;
+    for (offset, ch) in text[line_start..line_end].char_indices() {
+        if col16 >= position.character as usize {
+            return line_start + offset;
+        }
+        col16 += ch.len_utf16();
+    }
+    line_end
+}
+
+fn lsp_diagnostic(uri: &Url, diag: &lmm::Diagnostic) -> Diagnostic {
+    let severity = match diag.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+    };
+    let related = diag
+        .related
+        .iter()
+        .map(|related| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), span_to_range(related.span)),
+            message: related.message.to_string(),
+        })
+        .collect::<Vec<_>>();
+    Diagnostic {
+        range: span_to_range(diag.span),
+        severity: Some(severity),
+        code: Some(NumberOrString::String(diag.code.as_str().to_string())),
+        source: Some("LumosMark".to_string()),
+        message: diag.message.to_string(),
+        related_information: (!related.is_empty()).then_some(related),
+        ..Default::default()
+    }
+}
+
+fn edit_to_text_edit(edit: Edit) -> TextEdit {
+    TextEdit::new(span_to_range(edit.span), edit.new_text.to_string())
 }
 
 fn span_to_range(span: Span) -> Range {
@@ -216,3 +424,318 @@ fn span_to_range(span: Span) -> Range {
     let end = Position::new(span.end.line as u32, span.end.col16 as u32);
     Range::new(start, end)
 }
+
+#[cfg(test)]
+mod tests {
+    use lmm::{CODE_LANGUAGES, Locale, ParseOptions, parse_document};
+    use lsp::lsp_types::{
+        CompletionItem, CompletionTextEdit, FormattingOptions, HoverContents, Position, Range,
+        TextDocumentContentChangeEvent, TextEdit,
+    };
+
+    use crate::{
+        DocumentState, apply_changes, completion, formatting, hover, offset_at, reference,
+    };
+
+    fn state(text: &str, version: i32) -> DocumentState {
+        DocumentState {
+            version,
+            text: text.into(),
+            parsed: parse_document(text),
+        }
+    }
+
+    fn spaces(tab_size: u32) -> FormattingOptions {
+        FormattingOptions {
+            tab_size,
+            insert_spaces: true,
+            ..Default::default()
+        }
+    }
+
+    fn complete(text: &str, line: u32, character: u32) -> Vec<CompletionItem> {
+        let parsed = parse_document(text);
+        completion::completions(text, &parsed, Position::new(line, character), Locale::En)
+    }
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|item| item.label.as_str()).collect()
+    }
+
+    fn edit_range(item: &CompletionItem) -> Range {
+        match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => edit.range,
+            _ => panic!("completion has no plain text edit"),
+        }
+    }
+
+    fn apply_text_edits(text: &str, edits: &[TextEdit]) -> String {
+        let mut out = text.to_string();
+        for edit in edits.iter().rev() {
+            let start = offset_at(&out, edit.range.start);
+            let end = offset_at(&out, edit.range.end);
+            out.replace_range(start..end, &edit.new_text);
+        }
+        out
+    }
+
+    #[test]
+    fn formats_with_minimal_edits() {
+        let text = "@section 标题{\ntext\n}\n";
+        let parsed = parse_document(text);
+        let edits = formatting::document_edits(text, &parsed, &spaces(2));
+        assert_eq!(
+            edits,
+            vec![
+                TextEdit::new(
+                    Range::new(Position::new(0, 11), Position::new(0, 11)),
+                    " ".into()
+                ),
+                TextEdit::new(
+                    Range::new(Position::new(1, 0), Position::new(1, 0)),
+                    "  ".into()
+                ),
+            ]
+        );
+        assert_eq!(
+            apply_text_edits(text, &edits),
+            "@section 标题 {\n  text\n}\n"
+        );
+        let tabs = FormattingOptions {
+            insert_spaces: false,
+            ..spaces(4)
+        };
+        let edits = formatting::document_edits(text, &parsed, &tabs);
+        assert_eq!(
+            apply_text_edits(text, &edits),
+            "@section 标题 {\n\ttext\n}\n"
+        );
+
+        let formatted = "@section 标题 {\n  text\n}\n";
+        let parsed = parse_document(formatted);
+        assert!(formatting::document_edits(formatted, &parsed, &spaces(2)).is_empty());
+    }
+
+    #[test]
+    fn formats_selected_and_typed_lines() {
+        let text = "@section A{\none\ntwo\nthree\n}\n";
+        let parsed = parse_document(text);
+        let lines = |edits: Vec<TextEdit>| -> Vec<u32> {
+            edits.iter().map(|edit| edit.range.start.line).collect()
+        };
+
+        // A selection ending at column 0 leaves that line alone.
+        let range = Range::new(Position::new(1, 2), Position::new(3, 0));
+        let edits = formatting::range_edits(text, &parsed, range, &spaces(2));
+        assert_eq!(lines(edits), vec![1, 2]);
+        let range = Range::new(Position::new(2, 0), Position::new(3, 1));
+        let edits = formatting::range_edits(text, &parsed, range, &spaces(2));
+        assert_eq!(lines(edits), vec![2, 3]);
+        let range = Range::new(Position::new(0, 0), Position::new(0, 0));
+        let edits = formatting::range_edits(text, &parsed, range, &spaces(2));
+        assert_eq!(lines(edits), vec![0]);
+
+        // A newline formats the line it finished; other triggers their own.
+        let edits = formatting::on_type_edits(text, &parsed, "\n", Position::new(3, 0), &spaces(2));
+        assert_eq!(lines(edits), vec![2]);
+        let edits = formatting::on_type_edits(text, &parsed, "}", Position::new(1, 3), &spaces(2));
+        assert_eq!(lines(edits), vec![1]);
+        let edits = formatting::on_type_edits(text, &parsed, "\n", Position::new(0, 0), &spaces(2));
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn converts_utf16_positions_to_offsets() {
+        let text = "@node 中文 {\n  😀x\n}";
+        assert_eq!(offset_at(text, Position::new(0, 6)), "@node ".len());
+        assert_eq!(offset_at(text, Position::new(0, 7)), "@node 中".len());
+        // The emoji takes two UTF-16 units.
+        assert_eq!(
+            offset_at(text, Position::new(1, 4)),
+            "@node 中文 {\n  😀".len()
+        );
+        // Columns past the end clamp to the line end, lines past it to the text end.
+        assert_eq!(offset_at(text, Position::new(0, 99)), "@node 中文 {".len());
+        assert_eq!(offset_at(text, Position::new(2, 1)), text.len());
+        assert_eq!(offset_at(text, Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn applies_incremental_changes() {
+        let text = "@section 标题 {\n  😀 first\n  @code[lang=rust] {\n    x\n  }\n}\n";
+        let options = ParseOptions::default();
+        let state = state(text, 1);
+        let change =
+            |start: (u32, u32), end: (u32, u32), text: &str| TextDocumentContentChangeEvent {
+                range: Some(Range::new(
+                    Position::new(start.0, start.1),
+                    Position::new(end.0, end.1),
+                )),
+                range_length: None,
+                text: text.into(),
+            };
+        let changes = vec![
+            // Replace "first" after the emoji, then rename the section.
+            change((1, 5), (1, 10), "第一"),
+            change((0, 9), (0, 11), "Title"),
+            // Insert a block, then delete the code block's body line.
+            change((2, 0), (2, 0), "  @node {\n  }\n"),
+            change((5, 0), (6, 0), ""),
+        ];
+        let state = apply_changes(state, changes, options);
+        let expected =
+            "@section Title {\n  😀 第一\n  @node {\n  }\n  @code[lang=rust] {\n  }\n}\n";
+        assert_eq!(state.text, expected);
+        let full = parse_document(expected);
+        assert_eq!(state.parsed.syntax, full.syntax);
+        assert_eq!(state.parsed.document, full.document);
+        assert_eq!(state.parsed.diagnostics, full.diagnostics);
+
+        let replace = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "@node {".into(),
+        };
+        let state = apply_changes(state, vec![replace], options);
+        assert_eq!(state.text, "@node {");
+        assert_eq!(
+            state.parsed.diagnostics,
+            parse_document("@node {").diagnostics
+        );
+    }
+
+    #[test]
+    fn rejects_stale_versions() {
+        let state = state("@node {\n}\n", 3);
+        assert!(state.accepts(4));
+        assert!(state.accepts(10));
+        assert!(!state.accepts(3));
+        assert!(!state.accepts(2));
+    }
+
+    #[test]
+    fn hovers_blocks_and_escapes() {
+        let text = "@part Outer {\n  @list {\n    @part A[x=1] {\n      #id: a\n      a @@b\n      @part B {\n      }\n    }\n  }\n}\n@foo {\n}\n";
+        let parsed = parse_document(text);
+        let hover_at = |line: u32, character: u32| {
+            let hover = hover::hover(text, &parsed, Position::new(line, character))?;
+            let HoverContents::Markup(markup) = hover.contents else {
+                panic!("hover is not markup");
+            };
+            Some((markup.value, hover.range))
+        };
+
+        let (value, range) = hover_at(2, 6).unwrap();
+        assert!(value.starts_with("**@part** — built-in block"));
+        // The list resets the section depth, as it does when rendering.
+        assert!(value.contains("level 1 heading (`<h1>`, `#`)"));
+        assert!(value.contains("Params:\n- `x` = `1`"));
+        assert!(value.contains("Attributes:\n- `#id`: `a`"));
+        assert_eq!(range.unwrap().start, Position::new(2, 4));
+        let (value, _) = hover_at(5, 7).unwrap();
+        assert!(value.contains("level 2 heading (`<h2>`, `##`)"));
+
+        let (value, range) = hover_at(4, 9).unwrap();
+        assert_eq!(value, "Escape `@@` renders as `@`");
+        let escape = Range::new(Position::new(4, 8), Position::new(4, 10));
+        assert_eq!(range, Some(escape));
+
+        // Inside a body, the innermost block is described without a range.
+        let (value, range) = hover_at(4, 6).unwrap();
+        assert!(value.contains("Arguments: `A`"));
+        assert_eq!(range, None);
+
+        let (value, _) = hover_at(10, 1).unwrap();
+        assert!(value.contains("custom block"));
+        assert_eq!(hover_at(12, 0), None);
+        // Code content is verbatim, so `@@` there is not an escape.
+        let text = "@code {\n  a @@b\n}\n";
+        let parsed = parse_document(text);
+        let hover = hover::hover(text, &parsed, Position::new(1, 5)).unwrap();
+        let HoverContents::Markup(markup) = hover.contents else {
+            panic!("hover is not markup");
+        };
+        assert!(markup.value.starts_with("**@code**"));
+        assert_eq!(hover.range, None);
+    }
+
+    #[test]
+    fn completes_by_context() {
+        // Block names after `@`, replacing the partial name.
+        let items = complete("@pa", 0, 3);
+        let names = labels(&items);
+        assert!(names.contains(&"part") && names.contains(&"list") && names.contains(&"b"));
+        let range = Range::new(Position::new(0, 1), Position::new(0, 3));
+        assert!(items.iter().all(|item| edit_range(item) == range));
+        // Inline markers only, within a line of text.
+        let names = labels(&complete("中文 @l", 0, 5)).join(",");
+        assert_eq!(names, "b,i,u,link,ref");
+
+        // Parameter names, skipping those already given, then their values.
+        assert_eq!(labels(&complete("@list[", 0, 6)), ["bullet", "line"]);
+        assert_eq!(labels(&complete("@list[bullet, ", 0, 14)), ["line"]);
+        assert_eq!(labels(&complete("@code[", 0, 6)), ["lang="]);
+        let items = complete("@code[lang=ru", 0, 13);
+        assert_eq!(labels(&items), CODE_LANGUAGES);
+        let range = Range::new(Position::new(0, 11), Position::new(0, 13));
+        assert_eq!(edit_range(&items[0]), range);
+
+        // Attributes only right after a header or at the top of the document.
+        assert_eq!(labels(&complete("#", 0, 1)), ["title", "author", "version"]);
+        let text = "@part A {\n  #id: a\n  #\n  text\n  #\n}\n";
+        assert!(labels(&complete(text, 2, 3)).contains(&"id"));
+        assert!(!labels(&complete(text, 2, 3)).contains(&"title"));
+        assert!(complete(text, 4, 3).is_empty());
+    }
+
+    #[test]
+    fn completes_closers_in_the_client_locale() {
+        let text = "@part A +{\n  x\n  }\n}+\n@code {\n  @\n";
+        let parsed = parse_document(text);
+        let closer = |line, character, locale| {
+            let position = Position::new(line, character);
+            let items = completion::completions(text, &parsed, position, locale);
+            let item = items.into_iter().find(|item| item.label.starts_with('}'))?;
+            let range = edit_range(&item);
+            Some((item.label, item.detail.unwrap(), range))
+        };
+        let (label, detail, range) = closer(2, 3, Locale::En).unwrap();
+        assert_eq!((label.as_str(), detail.as_str()), ("}+", "Close '@part'"));
+        assert_eq!(range, Range::new(Position::new(2, 2), Position::new(2, 3)));
+        let (_, detail, _) = closer(2, 3, Locale::ZhCn).unwrap();
+        assert_eq!(detail, "结束 '@part'");
+        assert_eq!(closer(1, 3, Locale::En), None);
+
+        // Code content gets no block names, only the closer.
+        assert!(complete(text, 5, 3).is_empty());
+        assert_eq!(labels(&complete(text, 5, 0)), ["}"]);
+    }
+
+    #[test]
+    fn resolves_cross_references() {
+        let text = "@part 简介 {\n  #id: intro\n  见 @ref(intro) {此处}\n}\n@part Details {\n  @ref(intro) {} and @ref(details) {}\n}\n";
+        let parsed = parse_document(text);
+        let at = |line, character| Position::new(line, character);
+        let range = |line, start, end| Range::new(at(line, start), at(line, end));
+
+        // Ids complete inside `@ref(`, replacing what is typed so far.
+        let items = complete(text, 2, 9);
+        assert_eq!(labels(&items), ["intro", "details"]);
+        assert_eq!(items[0].detail.as_deref(), Some("简介"));
+        assert_eq!(edit_range(&items[0]), range(2, 9, 9));
+        let items = complete("@part A {\n}\n@ref(de", 2, 7);
+        assert_eq!(edit_range(&items[0]), range(2, 5, 7));
+
+        let declaration = reference::definition(&parsed, at(5, 4)).unwrap();
+        assert_eq!(declaration, range(1, 0, 12));
+        assert_eq!(reference::definition(&parsed, at(1, 5)), Some(declaration));
+        let derived = reference::definition(&parsed, at(5, 24)).unwrap();
+        assert_eq!(derived.start, at(4, 0));
+        assert_eq!(reference::definition(&parsed, at(3, 0)), None);
+
+        let uses = reference::find_references(&parsed, at(1, 5), false);
+        assert_eq!(uses, [range(2, 4, 20), range(5, 2, 16)]);
+        let all = reference::find_references(&parsed, at(2, 6), true);
+        assert_eq!(all, [declaration, range(2, 4, 20), range(5, 2, 16)]);
+    }
+}
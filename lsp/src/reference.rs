@@ -0,0 +1,52 @@
+use lmm::{Anchor, ParseResult, anchors, references};
+use lsp::lsp_types::{Position, Range};
+
+use crate::context::contains;
+use crate::span_to_range;
+
+/// Returns where the id under `position` is declared.
+pub(crate) fn definition(parsed: &ParseResult, position: Position) -> Option<Range> {
+    let anchors = anchors(&parsed.document);
+    let id = id_at(parsed, &anchors, position)?;
+    anchors
+        .iter()
+        .find(|anchor| anchor.id == id)
+        .map(|anchor| span_to_range(anchor.span))
+}
+
+/// Returns the cross-references to the id under `position`, and its
+/// declarations too when `include_declaration` is set.
+pub(crate) fn find_references(
+    parsed: &ParseResult,
+    position: Position,
+    include_declaration: bool,
+) -> Vec<Range> {
+    let anchors = anchors(&parsed.document);
+    let Some(id) = id_at(parsed, &anchors, position) else {
+        return Vec::new();
+    };
+    let declarations = anchors
+        .iter()
+        .filter(|anchor| include_declaration && anchor.id == id)
+        .map(|anchor| anchor.span);
+    let uses = references(&parsed.document)
+        .into_iter()
+        .filter(|reference| reference.id == id)
+        .map(|reference| reference.span);
+    declarations.chain(uses).map(span_to_range).collect()
+}
+
+/// Finds the id of the `@ref` marker, `#id` attribute or section header under
+/// `position`.
+fn id_at(parsed: &ParseResult, anchors: &[Anchor], position: Position) -> Option<String> {
+    let reference = references(&parsed.document)
+        .into_iter()
+        .find(|reference| contains(reference.span, position));
+    if let Some(reference) = reference {
+        return Some(reference.id.to_string());
+    }
+    anchors
+        .iter()
+        .find(|anchor| contains(anchor.span, position))
+        .map(|anchor| anchor.id.to_string())
+}